@@ -1,7 +1,7 @@
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use intcode::Machine;
+use intcode::search;
 
 fn main() -> Result<(), std::io::Error> {
     let stdin = std::io::stdin();
@@ -29,22 +29,21 @@ fn main() -> Result<(), std::io::Error> {
     // machine.execute();
     // println!("value at memory position 0: {}", machine.memory[0]);
 
-    for noun in 0..99 {
-        for verb in 0..99 {
-            println!("noun: {}, verb: {}", noun, verb);
-            let (_, mut machine) = Machine::new(original_state.clone());
-            machine.set_noun(noun);
-            machine.set_verb(verb);
-            machine.execute();
-            if machine.output() == 19690720 {
-                println!(
-                    "100 * noun + verb == 100 * {} + {} == {}",
-                    noun,
-                    verb,
-                    100 * noun + verb
-                );
-                return Ok(());
-            }
+    let found = search::find_first(
+        &original_state,
+        search::noun_verb(0..100, 0..100),
+        search::default_threads(),
+        |machine| machine.output() == Ok(19690720),
+    );
+    if let Some(found) = found {
+        if let search::Candidate::Patch(patch) = found.candidate {
+            let (noun, verb) = (patch[0].1, patch[1].1);
+            println!(
+                "100 * noun + verb == 100 * {} + {} == {}",
+                noun,
+                verb,
+                100 * noun + verb
+            );
         }
     }
 
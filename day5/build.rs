@@ -0,0 +1,16 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// Translates the puzzle input to Rust, see `--native` in main.rs.
+fn main() {
+    println!("cargo:rerun-if-changed=input1.txt");
+    let program = fs::read_to_string("input1.txt")
+        .unwrap()
+        .trim()
+        .split(',')
+        .map(|v| v.parse::<i64>().unwrap())
+        .collect::<Vec<i64>>();
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("native.rs");
+    fs::write(out, intcode::aot::translate(&program)).unwrap();
+}
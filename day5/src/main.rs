@@ -1,7 +1,12 @@
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use intcode::Machine;
+use intcode::{InputType, Machine, MachineBuilder};
+
+// the puzzle input translated to Rust by build.rs
+mod native {
+    include!(concat!(env!("OUT_DIR"), "/native.rs"));
+}
 
 fn main() -> Result<(), std::io::Error> {
     // let stdin = std::io::stdin();
@@ -15,9 +20,22 @@ fn main() -> Result<(), std::io::Error> {
 
     let original_state = program_state.clone();
 
-    let (_, mut machine) = Machine::new(original_state.clone());
-    machine.set_input_string("1\n".to_string());
-    machine.execute();
+    let native = std::env::args().any(|arg| arg == "--native");
+    let (_, mut machine) = if native {
+        MachineBuilder::new().trace(false).build(original_state.clone())
+    } else {
+        Machine::new(original_state.clone())
+    };
+    if std::env::args().any(|arg| arg == "--interactive") {
+        machine.set_input(InputType::Stdin);
+    } else {
+        machine.set_input_string("1\n".to_string());
+    }
+    if native {
+        native::run(&mut machine).unwrap();
+    } else {
+        machine.execute().unwrap();
+    }
 
     println!("program output: {:?}", machine.get_output());
 
@@ -18,7 +18,7 @@ fn main() -> Result<(), std::io::Error> {
         perm.iter().fold(0, |in_signal, phase_setting| {
             let (rx, mut machine) = Machine::new(original_state.clone());
             machine.set_input_string(format!("{}\n{}", phase_setting, in_signal));
-            machine.execute();
+            machine.execute().unwrap();
             if let Some(last) = rx.iter().last() {
                 last
             } else {
@@ -53,7 +53,7 @@ fn main() -> Result<(), std::io::Error> {
         for machine in machines.drain(0..4) {
             machine.execute_async();
         }
-        machines[0].execute(); // block on the last one finishing
+        machines[0].execute().unwrap(); // block on the last one finishing
 
         println!("{:?}", machines[0]);
         if let Some(last) = machines[0].get_output().iter().last() {
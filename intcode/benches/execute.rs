@@ -0,0 +1,38 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+use intcode::MachineBuilder;
+
+// Counts to 100000 in a loop, then outputs the counter.
+const COUNT: [i64; 21] = [1101, 0, 0, 19, 1001, 19, 1, 19, 1007, 19, 100_000, 20, 1005, 20, 4, 4, 19, 99, 0, 0, 0];
+
+// Sum of i * i for i below 20000, mixing position and immediate operands.
+const SUM_OF_SQUARES: [i64; 26] = [
+    2, 24, 24, 25, 1, 23, 25, 23, 1001, 24, 1, 24, 1007, 24, 20_000, 22, 1005, 22, 0, 4, 23, 99, 0, 0, 0, 0,
+];
+
+fn bench_program(c: &mut Criterion, name: &str, program: &[i64]) {
+    let mut group = c.benchmark_group(name);
+    group.bench_function("execute", |b| {
+        b.iter_batched(
+            || MachineBuilder::new().trace(false).build_detached(program.to_vec()),
+            |mut machine| machine.execute().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("execute_compiled", |b| {
+        b.iter_batched(
+            || MachineBuilder::new().trace(false).build_detached(program.to_vec()),
+            |mut machine| machine.execute_compiled().unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    bench_program(c, "count", &COUNT);
+    bench_program(c, "sum_of_squares", &SUM_OF_SQUARES);
+}
+
+criterion_group!(execute, benches);
+criterion_main!(execute);
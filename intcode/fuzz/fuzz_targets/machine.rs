@@ -0,0 +1,59 @@
+// Runs arbitrary memory images and input under every machine configuration. Any panic or an
+// instruction budget overrun is a bug; every error the machine returns is fine.
+//
+//     cargo fuzz run machine
+//
+// The property test `it_never_panics_or_runs_forever` in src/lib.rs covers the same ground
+// with structured inputs on every `cargo test`.
+
+#![no_main]
+
+use intcode::{InputExhausted, InputType, MachineBuilder};
+use libfuzzer_sys::fuzz_target;
+
+const BUDGET: u64 = 2000;
+
+fuzz_target!(|data: &[u8]| {
+    // the first word picks a configuration and how many of the rest are input, then memory
+    let words = data
+        .chunks(8)
+        .map(|chunk| {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            i64::from_le_bytes(bytes)
+        })
+        .collect::<Vec<_>>();
+    let (setup, rest) = match words.split_first() {
+        Some((&setup, rest)) => (setup as u64, rest),
+        None => return,
+    };
+    let inputs = (setup >> 8) as usize % (rest.len() + 1);
+    let (input, memory) = rest.split_at(inputs);
+
+    let mut m = MachineBuilder::new()
+        .trace(false)
+        .memory_multiplier(2)
+        .instruction_budget(BUDGET)
+        .checked_arithmetic(setup & 1 != 0)
+        .record_trace(setup & 2 != 0)
+        .input_exhausted(match setup >> 2 & 3 {
+            0 => InputExhausted::Pause,
+            1 => InputExhausted::Error,
+            _ => InputExhausted::Block,
+        })
+        .build_detached(memory.to_vec());
+    m.set_lenient_modes(setup & 16 != 0);
+    m.set_detect_self_modification(setup & 32 != 0);
+    m.set_input(InputType::Values(input.iter().copied().collect()));
+    if let Some(tx) = m.input_sender() {
+        // enough that it never waits
+        for i in 0..=BUDGET as i64 {
+            tx.send(i).unwrap();
+        }
+    }
+    let _ = m.set_noun(1);
+    let _ = m.set_verb(2);
+    let _ = if setup & 64 != 0 { m.execute_compiled() } else { m.execute() };
+    let _ = m.output();
+    assert!(m.instructions_executed() <= BUDGET);
+});
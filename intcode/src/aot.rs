@@ -0,0 +1,344 @@
+//! Ahead-of-time translation of intcode programs to Rust.
+//!
+//! [`translate`] turns a program image into the source of a Rust module with a single function,
+//! `pub fn run(machine: &mut intcode::Machine) -> Result<intcode::Status, intcode::Error>`, that
+//! does what [`Machine::execute`] does. Every instruction reachable from address 0 (see
+//! [`crate::cfg`]) becomes a match arm with its operands baked in. Programs often patch an
+//! instruction right before running it, like the day 5 input does to the word at address 6; the
+//! walk can't get past a word that doesn't decode yet, so it picks up again after it, at every
+//! length the patched instruction could have. The interpreter takes over for
+//! everything else: addresses only reached through computed jumps, instructions whose memory no
+//! longer matches the image the module was generated from, registered opcodes, and machines with
+//! tracing or self-modification detection turned on.
+//!
+//! Meant to be used from a build script:
+//!
+//! ```ignore
+//! // build.rs
+//! let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("program.rs");
+//! fs::write(out, intcode::aot::translate(&program)).unwrap();
+//!
+//! // main.rs
+//! mod program {
+//!     include!(concat!(env!("OUT_DIR"), "/program.rs"));
+//! }
+//! ```
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::Write;
+
+use crate::cfg::{Cfg, Exit};
+use crate::extension::Param;
+use crate::{builtin_params, decode, Error, Machine, Status};
+
+/// Rust source for a module running `program` natively.
+pub fn translate(program: &[i64]) -> String {
+    let translated = reachable(program)
+        .into_iter()
+        .filter_map(|addr| translate_instruction(program, addr).map(|(len, body)| (addr, len, body)))
+        .collect::<Vec<_>>();
+
+    let mut src = String::new();
+    writeln!(src, "// Generated by intcode::aot::translate from a {} cell program. Do not edit.", program.len()).unwrap();
+    writeln!(src).unwrap();
+    writeln!(src, "use intcode::aot::Native;").unwrap();
+    writeln!(src, "use intcode::{{Error, Machine, Status}};").unwrap();
+    writeln!(src).unwrap();
+    writeln!(src, "// every translated instruction with the words it was translated from").unwrap();
+    writeln!(src, "const CODE: &[(usize, &[i64])] = &[").unwrap();
+    for (addr, len, _) in &translated {
+        writeln!(src, "    ({}, &{:?}),", addr, &program[*addr..addr + len]).unwrap();
+    }
+    writeln!(src, "];").unwrap();
+    writeln!(src).unwrap();
+    writeln!(src, "#[allow(clippy::all)]").unwrap();
+    writeln!(src, "pub fn run(machine: &mut Machine) -> Result<Status, Error> {{").unwrap();
+    writeln!(src, "    let mut native = Native::new(machine, CODE);").unwrap();
+    writeln!(src, "    loop {{").unwrap();
+    writeln!(src, "        let pc = native.pc();").unwrap();
+    writeln!(src, "        if !native.translated(pc) {{").unwrap();
+    writeln!(src, "            if let Some(status) = native.step()? {{").unwrap();
+    writeln!(src, "                return Ok(status);").unwrap();
+    writeln!(src, "            }}").unwrap();
+    writeln!(src, "            continue;").unwrap();
+    writeln!(src, "        }}").unwrap();
+    writeln!(src, "        native.begin()?;").unwrap();
+    writeln!(src, "        match pc {{").unwrap();
+    for (addr, _, body) in &translated {
+        writeln!(src, "            {} => {{", addr).unwrap();
+        for line in body {
+            writeln!(src, "                {}", line).unwrap();
+        }
+        writeln!(src, "            }}").unwrap();
+    }
+    writeln!(src, "            _ => unreachable!(\"{{}} is not translated\", pc),").unwrap();
+    writeln!(src, "        }}").unwrap();
+    writeln!(src, "    }}").unwrap();
+    writeln!(src, "}}").unwrap();
+    src
+}
+
+// Addresses of the instructions to translate, in order. Guessing wrong only costs an arm that
+// never runs.
+fn reachable(program: &[i64]) -> Vec<usize> {
+    let mut entries = vec![0];
+    loop {
+        let cfg = Cfg::build_from(program, &entries);
+        let restarts = cfg
+            .blocks
+            .values()
+            .filter(|block| block.exit == Exit::Invalid)
+            .flat_map(|block| {
+                let stuck = *block.instructions.last().unwrap();
+                (2..=4).map(move |len| stuck + len)
+            })
+            .filter(|addr| *addr < program.len() && !entries.contains(addr))
+            .collect::<BTreeSet<_>>();
+        if restarts.is_empty() {
+            let mut addrs = cfg.blocks.values().flat_map(|block| block.instructions.iter().cloned()).collect::<Vec<_>>();
+            addrs.sort_unstable();
+            addrs.dedup();
+            return addrs;
+        }
+        entries.extend(restarts);
+    }
+}
+
+// The length of the instruction at `addr` and the statements running it, or None if it's left
+// to the interpreter.
+fn translate_instruction(program: &[i64], addr: usize) -> Option<(usize, Vec<String>)> {
+    let instr = decode(*program.get(addr)?)?;
+    let params = builtin_params(instr.opcode)?;
+    let len = params.len() + 1;
+    let args = program.get(addr + 1..addr + len)?;
+    let modes = [instr.mode_op1, instr.mode_op2, instr.mode_op3];
+    let mut ops = vec![];
+    for (i, param) in params.iter().enumerate() {
+        ops.push(match (param, modes[i]) {
+            (Param::Read, 1) => args[i].to_string(),
+            (Param::Read, 0) if args[i] >= 0 => format!("native.load({})?", args[i]),
+            (Param::Write, 0) if args[i] >= 0 => args[i].to_string(),
+            _ => return None,
+        });
+    }
+    let next = addr + len;
+    let jump = format!("native.jump({});", next);
+    let body = match instr.opcode {
+        1 => vec![format!("let val = native.add({}, {})?;", ops[0], ops[1]), format!("native.store({}, val)?;", ops[2]), jump],
+        2 => vec![format!("let val = native.mul({}, {})?;", ops[0], ops[1]), format!("native.store({}, val)?;", ops[2]), jump],
+        3 => vec![
+            "let val = match native.input()? {".to_string(),
+            "    Some(val) => val,".to_string(),
+            "    None => return Ok(Status::NeedsInput),".to_string(),
+            "};".to_string(),
+            format!("native.store({}, val)?;", ops[0]),
+            jump,
+        ],
+        4 => vec![format!("native.output({})?;", ops[0]), jump],
+        5 => vec![format!("native.jump(if {} != 0 {{ {} }} else {{ {} }});", ops[0], ops[1], next)],
+        6 => vec![format!("native.jump(if {} == 0 {{ {} }} else {{ {} }});", ops[0], ops[1], next)],
+        7 => vec![format!("native.store({}, ({} < {}) as i64)?;", ops[2], ops[0], ops[1]), jump],
+        8 => vec![format!("native.store({}, ({} == {}) as i64)?;", ops[2], ops[0], ops[1]), jump],
+        99 => vec!["return Ok(native.halt());".to_string()],
+        _ => return None,
+    };
+    Some((len, body))
+}
+
+/// What the code generated by [`translate`] runs on. Not meant to be used directly.
+pub struct Native<'a> {
+    machine: &'a mut Machine,
+    // the translated instructions the machine can run natively, by address
+    code: BTreeMap<usize, &'a [i64]>,
+}
+
+impl<'a> Native<'a> {
+    /// `code` lists every translated instruction as its address and the words it was
+    /// translated from. Instructions that don't match the machine's memory are interpreted.
+    pub fn new(machine: &'a mut Machine, code: &'a [(usize, &'a [i64])]) -> Self {
+        let native = !(machine.trace || machine.trace_events.is_some() || machine.detect_self_modification);
+        let code = code
+            .iter()
+            .filter(|&&(addr, words)| native && machine.read_range(addr..addr + words.len()) == Some(words))
+            // anything the interpreter wouldn't run as a plain built-in instruction either
+            .filter(|&&(addr, _)| machine.compile(addr as i64).is_some())
+            .copied()
+            .collect();
+        Native { machine, code }
+    }
+
+    pub fn pc(&self) -> i64 {
+        self.machine.program_counter
+    }
+
+    /// True if the instruction at `pc` can run natively: it was translated and memory still
+    /// holds the words it was translated from.
+    pub fn translated(&self, pc: i64) -> bool {
+        let pc = match usize::try_from(pc) {
+            Ok(pc) => pc,
+            Err(_) => return false,
+        };
+        match self.code.get(&pc) {
+            Some(words) => self.machine.read_range(pc..pc + words.len()) == Some(*words),
+            None => false,
+        }
+    }
+
+    /// Interpret the instruction at the program counter.
+    pub fn step(&mut self) -> Result<Option<Status>, Error> {
+        self.machine.step()
+    }
+
+    /// Count the instruction about to run natively.
+    pub fn begin(&mut self) -> Result<(), Error> {
+        let machine = &mut self.machine;
+        if machine.instruction_budget.is_some_and(|budget| machine.instructions_executed >= budget) {
+            return Err(Error::BudgetExhausted { pc: machine.program_counter });
+        }
+        machine.instructions_executed += 1;
+        Ok(())
+    }
+
+    pub fn load(&self, addr: i64) -> Result<i64, Error> {
+        self.machine.load(addr)
+    }
+
+    pub fn store(&mut self, addr: i64, val: i64) -> Result<(), Error> {
+        self.machine.store(addr, val)
+    }
+
+    pub fn add(&self, a: i64, b: i64) -> Result<i64, Error> {
+        self.machine.add(&a, &b)
+    }
+
+    pub fn mul(&self, a: i64, b: i64) -> Result<i64, Error> {
+        self.machine.mul(&a, &b)
+    }
+
+    /// None if the machine has to pause for input. The instruction then runs again on resume.
+    pub fn input(&mut self) -> Result<Option<i64>, Error> {
+        let val = self.machine.read_input()?;
+        if val.is_none() {
+            self.machine.pause_for_input();
+        }
+        Ok(val)
+    }
+
+    pub fn output(&mut self, val: i64) -> Result<(), Error> {
+        self.machine.emit_output(val)
+    }
+
+    pub fn jump(&mut self, addr: i64) {
+        self.machine.program_counter = addr;
+    }
+
+    pub fn halt(&mut self) -> Status {
+        self.machine.halt();
+        Status::Halted
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::MachineBuilder;
+
+    // day 5 example: outputs 1 if the input is 8, otherwise 0
+    const IS_EIGHT: [i64; 9] = [3, 3, 1108, -1, 8, 3, 4, 3, 99];
+
+    #[test]
+    fn it_translates_reachable_instructions() {
+        let src = translate(&IS_EIGHT);
+        assert!(src.contains("const CODE: &[(usize, &[i64])] = &[\n    (0, &[3, 3]),\n    (2, &[1108, -1, 8, 3]),\n    (6, &[4, 3]),\n    (8, &[99]),\n];"));
+        assert!(src.contains("            2 => {\n                native.store(3, (-1 == 8) as i64)?;\n                native.jump(6);\n            }"));
+        assert!(src.contains("            6 => {\n                native.output(native.load(3)?)?;\n                native.jump(8);\n            }"));
+        assert!(src.contains("            8 => {\n                return Ok(native.halt());\n            }"));
+    }
+
+    #[test]
+    fn it_leaves_untranslatable_instructions_to_the_interpreter() {
+        // an immediate write is an error, and where the jump goes is only known at runtime
+        let src = translate(&[11101, 1, 1, 0, 105, 1, 9, 99, 99, 7]);
+        assert!(src.contains("const CODE: &[(usize, &[i64])] = &[\n    (4, &[105, 1, 9]),\n];"));
+        assert!(src.contains("native.jump(if 1 != 0 { native.load(9)? } else { 7 });"));
+    }
+
+    #[test]
+    fn it_only_runs_instructions_that_match_memory() {
+        let (_, mut m) = MachineBuilder::new().trace(false).build(IS_EIGHT.to_vec());
+        m.poke(4, 7).unwrap();
+        let mut native = Native::new(&mut m, &[(0, &[3, 3]), (2, &[1108, -1, 8, 3]), (6, &[4, 3]), (8, &[99])]);
+        assert!(native.translated(0));
+        assert!(!native.translated(2));
+        assert!(!native.translated(1));
+
+        // writes drop the instruction they change, and putting the words back restores it
+        native.store(1, 5).unwrap();
+        assert!(!native.translated(0));
+        assert!(native.translated(6));
+        native.store(1, 3).unwrap();
+        assert!(native.translated(0));
+
+        // nothing runs natively while tracing
+        let (_, mut m) = MachineBuilder::new().build(IS_EIGHT.to_vec());
+        assert!(!Native::new(&mut m, &[(0, &[3, 3])]).translated(0));
+    }
+
+    #[test]
+    fn it_leaves_the_compiled_instruction_cache_alone() {
+        let (_, mut m) = MachineBuilder::new().trace(false).build(IS_EIGHT.to_vec());
+        assert_eq!(m.execute_compiled(), Ok(Status::NeedsInput));
+        let cached = |m: &Machine| m.compiled.iter().map(Option::is_some).collect::<Vec<_>>();
+        let before = cached(&m);
+        let code = [(0, &[3, 3][..]), (2, &[1108, -1, 8, 3]), (6, &[4, 3]), (8, &[99])];
+        assert!(Native::new(&mut m, &code).translated(2));
+        assert_eq!(cached(&m), before);
+        m.set_input_string("8\n".to_string());
+        assert_eq!(m.execute_compiled(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![1]);
+    }
+
+    #[test]
+    fn it_translates_past_instructions_patched_at_runtime() {
+        // like the day 5 input: the instruction at 6 only decodes once input has been added to it
+        #[rustfmt::skip]
+        let program = [
+            3, 20,              // 0: IN [20]
+            1, 20, 6, 6,        // 2: ADD [20] [6] [6]
+            1100, 5, 0, 21,     // 6: becomes ADD #5 #0 [21]
+            4, 21,              // 10: OUT [21]
+            1001, 21, -1, 21,   // 12: ADD [21] #-1 [21]
+            1005, 21, 10,       // 16: JT [21] #10
+            99,                 // 19
+            0, 0,
+        ];
+        let src = translate(&program);
+        assert!(src.contains("            10 => {"));
+        assert!(src.contains("            16 => {"));
+
+        // step through it the way the generated code would, counting what runs natively
+        let code = reachable(&program)
+            .into_iter()
+            .filter_map(|addr| translate_instruction(&program, addr).map(|(len, _)| (addr, &program[addr..addr + len])))
+            .collect::<Vec<_>>();
+        let (_, mut m) = MachineBuilder::new().trace(false).build(program.to_vec());
+        m.set_input_string("1\n".to_string());
+        let mut native = Native::new(&mut m, &code);
+        let (mut steps, mut translated) = (0, 0);
+        loop {
+            steps += 1;
+            translated += native.translated(native.pc()) as usize;
+            if native.step().unwrap().is_some() {
+                break;
+            }
+        }
+        assert_eq!(m.get_output(), &vec![5, 4, 3, 2, 1]);
+        // everything but the patched instruction
+        assert_eq!((translated, steps), (18, 19));
+    }
+}
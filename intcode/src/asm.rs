@@ -0,0 +1,412 @@
+//! An assembler for intcode.
+//!
+//! The syntax follows the disassembler in [`crate::cfg`], minus the addresses:
+//!
+//! ```text
+//! ; comments run to the end of the line
+//!         IN [n]
+//! loop:   OUT [n]             ; labels name the address of what follows them
+//!         ADD [n] #-1 [n]
+//!         JT [n] #loop
+//!         HALT
+//! n:      DATA 0
+//! size = n+1                  ; constants
+//! ```
+//!
+//! Operands are `#value` (immediate), `[value]` (position) or `[rb+value]` (relative), where a
+//! value is a sum of numbers, labels and constants like `end-start+1`. `DATA` emits its values
+//! as they are. `GLOBAL name...` exports symbols from a module assembled with
+//! [`assemble_object`]; [`assemble`] checks they're defined and otherwise ignores it.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::builtin_params;
+use crate::cfg::mnemonic;
+use crate::debug::{DebugInfo, Line};
+use crate::extension::Param;
+use crate::link::{Object, Relocation, Symbol, Target};
+
+/// An assembled program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assembly {
+    pub program: Vec<i64>,
+    /// Every label and constant with its value.
+    pub symbols: BTreeMap<String, i64>,
+    /// Labels and source lines, [`DebugInfo::to_map`] writes them out as a map file.
+    pub debug: DebugInfo,
+}
+
+/// Lines are counted from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    InvalidOperand { line: usize, operand: String },
+    OperandCount { line: usize, expected: usize, found: usize },
+    /// An immediate-mode operand for a parameter that is written to.
+    ImmediateWrite { line: usize, operand: String },
+    UndefinedSymbol { line: usize, name: String },
+    DuplicateSymbol { line: usize, name: String },
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmError::UnknownMnemonic { line, mnemonic } => write!(f, "line {}: unknown mnemonic {}", line, mnemonic),
+            AsmError::InvalidOperand { line, operand } => write!(f, "line {}: invalid operand {}", line, operand),
+            AsmError::OperandCount { line, expected, found } => {
+                write!(f, "line {}: expected {} operands, found {}", line, expected, found)
+            }
+            AsmError::ImmediateWrite { line, operand } => {
+                write!(f, "line {}: {} is written to but in immediate mode", line, operand)
+            }
+            AsmError::UndefinedSymbol { line, name } => write!(f, "line {}: undefined symbol {}", line, name),
+            AsmError::DuplicateSymbol { line, name } => write!(f, "line {}: {} is defined twice", line, name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AsmError {}
+
+// A sum of terms, each added or subtracted.
+#[derive(Clone, Debug)]
+struct Value(Vec<(bool, Term)>);
+
+#[derive(Clone, Debug)]
+enum Term {
+    Num(i64),
+    Symbol(String),
+}
+
+#[derive(Debug)]
+enum Item {
+    Instruction { opcode: i32, operands: Vec<(i32, Value)> },
+    Data(Vec<Value>),
+}
+
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    let mut terms = vec![];
+    let mut rest = text;
+    let mut negative = false;
+    if let Some(stripped) = rest.strip_prefix('-') {
+        negative = true;
+        rest = stripped;
+    }
+    loop {
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = &rest[..end];
+        if let Ok(num) = term.parse::<i64>() {
+            terms.push((negative, Term::Num(num)));
+        } else if is_symbol(term) {
+            terms.push((negative, Term::Symbol(term.to_string())));
+        } else {
+            return None;
+        }
+        if end == rest.len() {
+            return Some(Value(terms));
+        }
+        negative = rest[end..].starts_with('-');
+        rest = &rest[end + 1..];
+    }
+}
+
+// (mode, value) of an operand
+fn parse_operand(text: &str) -> Option<(i32, Value)> {
+    if let Some(value) = text.strip_prefix('#') {
+        return Some((1, parse_value(value)?));
+    }
+    let inner = text.strip_prefix('[')?.strip_suffix(']')?;
+    match inner.strip_prefix("rb") {
+        Some(offset) if offset.starts_with('+') => Some((2, parse_value(&offset[1..])?)),
+        Some(offset) if offset.starts_with('-') => Some((2, parse_value(offset)?)),
+        _ => Some((0, parse_value(inner)?)),
+    }
+}
+
+fn opcode(name: &str) -> Option<i32> {
+    (1..=9).chain(Some(99)).find(|&opcode| mnemonic(opcode) == Some(name))
+}
+
+// each symbol's value and how many times the load address is added to it: 1 for labels, 0 for
+// plain numbers
+struct Symbols(BTreeMap<String, (i64, i64)>);
+
+impl Symbols {
+    // unknown symbols are collected into `externals` if given, otherwise they're an error
+    fn eval(
+        &self,
+        value: &Value,
+        line: usize,
+        mut externals: Option<&mut Vec<(bool, String)>>,
+    ) -> Result<(i64, i64), AsmError> {
+        let (mut sum, mut bases) = (0i64, 0i64);
+        for (negative, term) in &value.0 {
+            let (val, base) = match term {
+                Term::Num(num) => (*num, 0),
+                Term::Symbol(name) => match (self.0.get(name), externals.as_deref_mut()) {
+                    (Some(&symbol), _) => symbol,
+                    (None, Some(externals)) => {
+                        externals.push((*negative, name.clone()));
+                        (0, 0)
+                    }
+                    (None, None) => return Err(AsmError::UndefinedSymbol { line, name: name.clone() }),
+                },
+            };
+            if *negative {
+                sum = sum.wrapping_sub(val);
+                bases -= base;
+            } else {
+                sum = sum.wrapping_add(val);
+                bases += base;
+            }
+        }
+        Ok((sum, bases))
+    }
+
+    fn define(&mut self, name: &str, symbol: (i64, i64), line: usize) -> Result<(), AsmError> {
+        if self.0.insert(name.to_string(), symbol).is_some() {
+            return Err(AsmError::DuplicateSymbol { line, name: name.to_string() });
+        }
+        Ok(())
+    }
+}
+
+// the result of the first pass
+struct Parsed<'a> {
+    items: Vec<(usize, Item)>,
+    // only labels, constants are evaluated once they're all known
+    symbols: Symbols,
+    constants: Vec<(usize, &'a str, Value)>,
+    globals: Vec<(usize, &'a str)>,
+    lines: BTreeMap<i64, Line>,
+    len: usize,
+}
+
+fn parse(src: &str) -> Result<Parsed<'_>, AsmError> {
+    let mut symbols = Symbols(BTreeMap::new());
+    let mut items = vec![];
+    let mut constants = vec![];
+    let mut globals = vec![];
+    let mut lines = BTreeMap::new();
+    let mut addr = 0;
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let mut code = text.split(';').next().unwrap().trim();
+        while let Some(colon) = code.find(':') {
+            let label = code[..colon].trim();
+            if !is_symbol(label) {
+                break;
+            }
+            symbols.define(label, (addr as i64, 1), line)?;
+            code = code[colon + 1..].trim();
+        }
+        if code.is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = code.split_once('=') {
+            let (name, text) = (name.trim(), value.trim());
+            let value = parse_value(text).ok_or_else(|| AsmError::InvalidOperand { line, operand: text.to_string() })?;
+            if !is_symbol(name) {
+                return Err(AsmError::InvalidOperand { line, operand: name.to_string() });
+            }
+            constants.push((line, name, value));
+            continue;
+        }
+        let mut words = code.split_whitespace();
+        let name = words.next().unwrap();
+        let operands = words.collect::<Vec<_>>();
+        let invalid = |operand: &str| AsmError::InvalidOperand { line, operand: operand.to_string() };
+        let item = if name == "GLOBAL" {
+            for operand in operands {
+                if !is_symbol(operand) {
+                    return Err(invalid(operand));
+                }
+                globals.push((line, operand));
+            }
+            continue;
+        } else if name == "DATA" {
+            Item::Data(operands.iter().map(|v| parse_value(v).ok_or_else(|| invalid(v))).collect::<Result<_, _>>()?)
+        } else {
+            let opcode = opcode(name).ok_or_else(|| AsmError::UnknownMnemonic { line, mnemonic: name.to_string() })?;
+            let params = builtin_params(opcode).unwrap();
+            if operands.len() != params.len() {
+                return Err(AsmError::OperandCount { line, expected: params.len(), found: operands.len() });
+            }
+            let mut parsed = vec![];
+            for (operand, param) in operands.iter().zip(params) {
+                let (mode, value) = parse_operand(operand).ok_or_else(|| invalid(operand))?;
+                if mode == 1 && *param == Param::Write {
+                    return Err(AsmError::ImmediateWrite { line, operand: operand.to_string() });
+                }
+                parsed.push((mode, value));
+            }
+            Item::Instruction { opcode, operands: parsed }
+        };
+        lines.insert(addr as i64, Line { number: line, text: text.split(';').next().unwrap().trim().to_string() });
+        addr += match &item {
+            Item::Instruction { operands, .. } => operands.len() + 1,
+            Item::Data(values) => values.len(),
+        };
+        items.push((line, item));
+    }
+    Ok(Parsed { items, symbols, constants, globals, lines, len: addr })
+}
+
+enum Word<'a> {
+    Opcode(i64),
+    Value(&'a Value),
+}
+
+impl Parsed<'_> {
+    // before constants are added to the symbols, so they're all labels
+    fn debug_info(&self) -> DebugInfo {
+        let labels = self.symbols.0.iter().map(|(name, &(addr, _))| (name.clone(), addr)).collect();
+        DebugInfo { labels, lines: self.lines.clone(), len: self.len as i64 }
+    }
+
+    // the words of the program in order, each with the line it came from
+    fn words(&self) -> Vec<(usize, Word<'_>)> {
+        let mut words = Vec::with_capacity(self.len);
+        for (line, item) in &self.items {
+            match item {
+                Item::Instruction { opcode, operands } => {
+                    let modes = operands.iter().rev().fold(0, |word, (mode, _)| word * 10 + *mode as i64);
+                    words.push((*line, Word::Opcode(modes * 100 + *opcode as i64)));
+                    words.extend(operands.iter().map(|(_, value)| (*line, Word::Value(value))));
+                }
+                Item::Data(values) => words.extend(values.iter().map(|value| (*line, Word::Value(value)))),
+            }
+        }
+        words
+    }
+}
+
+pub fn assemble(src: &str) -> Result<Assembly, AsmError> {
+    let mut parsed = parse(src)?;
+    let debug = parsed.debug_info();
+    for (line, name, value) in core::mem::take(&mut parsed.constants) {
+        let val = parsed.symbols.eval(&value, line, None)?;
+        parsed.symbols.define(name, val, line)?;
+    }
+    for &(line, name) in &parsed.globals {
+        if !parsed.symbols.0.contains_key(name) {
+            return Err(AsmError::UndefinedSymbol { line, name: name.to_string() });
+        }
+    }
+
+    let mut program = Vec::with_capacity(parsed.len);
+    for (line, word) in parsed.words() {
+        program.push(match word {
+            Word::Opcode(word) => word,
+            Word::Value(value) => parsed.symbols.eval(value, line, None)?.0,
+        });
+    }
+    let symbols = parsed.symbols.0.iter().map(|(name, &(val, _))| (name.clone(), val)).collect();
+    Ok(Assembly { program, symbols, debug })
+}
+
+/// Assembles a module to be linked with others by [`crate::link::link`]. Symbols that aren't
+/// defined here are left for the linker to resolve, and only those named by `GLOBAL` can be
+/// used from other modules.
+pub fn assemble_object(src: &str) -> Result<Object, AsmError> {
+    let mut parsed = parse(src)?;
+    let debug = parsed.debug_info();
+    for (line, name, value) in core::mem::take(&mut parsed.constants) {
+        let (val, bases) = parsed.symbols.eval(&value, line, None)?;
+        // a constant is either an address in the module or a plain number
+        if bases != 0 && bases != 1 {
+            return Err(AsmError::InvalidOperand { line, operand: name.to_string() });
+        }
+        parsed.symbols.define(name, (val, bases), line)?;
+    }
+    let mut symbols = parsed
+        .symbols
+        .0
+        .iter()
+        .map(|(name, &(value, bases))| (name.clone(), Symbol { value, relocatable: bases == 1, global: false }))
+        .collect::<BTreeMap<_, _>>();
+    for &(line, name) in &parsed.globals {
+        symbols.get_mut(name).ok_or_else(|| AsmError::UndefinedSymbol { line, name: name.to_string() })?.global = true;
+    }
+
+    let mut code = Vec::with_capacity(parsed.len);
+    let mut relocations = vec![];
+    for (line, word) in parsed.words() {
+        let offset = code.len();
+        let value = match word {
+            Word::Opcode(word) => word,
+            Word::Value(value) => {
+                let mut externals = vec![];
+                let (val, bases) = parsed.symbols.eval(value, line, Some(&mut externals))?;
+                for _ in 0..bases.abs() {
+                    relocations.push(Relocation { offset, target: Target::Base, negative: bases < 0 });
+                }
+                for (negative, name) in externals {
+                    relocations.push(Relocation { offset, target: Target::Symbol(name), negative });
+                }
+                val
+            }
+        };
+        code.push(value);
+    }
+    Ok(Object { code, symbols, relocations, debug })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::cfg::format_instruction;
+    use crate::Machine;
+
+    const COUNTDOWN: &str = "
+; outputs n, n-1, ..., 1
+        IN [n]
+loop:   OUT [n]
+        ADD [n] #-1 [n]
+        JT [n] #loop
+        HALT
+n:      DATA 0
+end = n+1
+";
+
+    #[test]
+    fn it_assembles_programs() {
+        let assembly = assemble(COUNTDOWN).unwrap();
+        assert_eq!(assembly.program, vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        assert_eq!(assembly.symbols["loop"], 2);
+        assert_eq!(assembly.symbols["end"], 13);
+
+        let (_, mut m) = Machine::new(assembly.program);
+        m.set_input_string("3\n".to_string());
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![3, 2, 1]);
+
+        // disassembly assembles back to the same program
+        let program = [109, 2, 21201, -1, 3, 2, 204, 1, 99];
+        let src = [0, 2, 6, 8].iter().map(|&addr| format_instruction(&program, addr)).collect::<Vec<_>>().join("\n");
+        assert_eq!(src, "0: ARB #2\n2: ADD [rb-1] #3 [rb+2]\n6: OUT [rb+1]\n8: HALT");
+        let src = src.lines().map(|line| line.split_once(": ").unwrap().1).collect::<Vec<_>>().join("\n");
+        assert_eq!(assemble(&src).unwrap().program, program);
+    }
+
+    #[test]
+    fn it_reports_errors_with_line_numbers() {
+        let err = |src| assemble(src).unwrap_err();
+        assert_eq!(err("HALT\nNOP"), AsmError::UnknownMnemonic { line: 2, mnemonic: "NOP".to_string() });
+        assert_eq!(err("OUT"), AsmError::OperandCount { line: 1, expected: 1, found: 0 });
+        assert_eq!(err("OUT {1}"), AsmError::InvalidOperand { line: 1, operand: "{1}".to_string() });
+        assert_eq!(err("IN #1"), AsmError::ImmediateWrite { line: 1, operand: "#1".to_string() });
+        assert_eq!(err("\nJT #1 #nowhere"), AsmError::UndefinedSymbol { line: 2, name: "nowhere".to_string() });
+        assert_eq!(err("a: HALT\na: HALT"), AsmError::DuplicateSymbol { line: 2, name: "a".to_string() });
+    }
+}
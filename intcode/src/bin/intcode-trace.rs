@@ -0,0 +1,120 @@
+// Records execution traces as JSON Lines, compares them and draws memory heatmaps from them.
+//
+//     intcode-trace [--map <program.map>] record <program> <trace.jsonl> [input...]
+//     intcode-trace [--map <program.map>] diff <a.jsonl> <b.jsonl> [context]
+//     intcode-trace heatmap <program> <trace.jsonl> <image.ppm> [width]
+//
+// Programs are comma separated like the puzzle inputs. `diff` prints the first event where the
+// traces differ with `context` events around it (3 by default) and exits with 1, or exits with
+// 0 if they're the same. `heatmap` draws how the recorded run used memory, `width` cells to a
+// row (64 by default). With the map file of an assembled program (see `DebugInfo::to_map`)
+// `record` puts a label and source line to the error the run stopped with and `diff` to where
+// the traces diverge.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use intcode::debug::DebugInfo;
+use intcode::heatmap::Heatmap;
+use intcode::{first_divergence, format_divergence, json, MachineBuilder, TraceEvent};
+
+fn usage() -> ! {
+    eprintln!("usage: intcode-trace [--map <program.map>] record <program> <trace.jsonl> [input...]");
+    eprintln!("       intcode-trace [--map <program.map>] diff <a.jsonl> <b.jsonl> [context]");
+    eprintln!("       intcode-trace heatmap <program> <trace.jsonl> <image.ppm> [width]");
+    process::exit(2);
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("intcode-trace: {}", message);
+    process::exit(2);
+}
+
+fn load_program(path: &str) -> Vec<i64> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+    text.trim()
+        .split(',')
+        .map(|v| v.trim().parse::<i64>())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| fail(format!("{}: {}", path, e)))
+}
+
+fn load_trace(path: &str) -> Vec<TraceEvent> {
+    json::load_lines(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)))
+}
+
+fn load_map(path: &str) -> DebugInfo {
+    let map = fs::read_to_string(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+    DebugInfo::from_map(&map).unwrap_or_else(|e| fail(format!("{}: {}", path, e)))
+}
+
+fn record(program: &str, trace: &str, inputs: &[String], map: Option<DebugInfo>) {
+    let program = load_program(program);
+    let mut builder = MachineBuilder::new().trace(false).record_trace(true);
+    if let Some(map) = map {
+        builder = builder.debug_info(map);
+    }
+    let (_, mut machine) = builder.build(program);
+    machine.set_input_string(inputs.iter().map(|input| format!("{}\n", input)).collect());
+    let result = machine.execute();
+    json::save_lines(trace, machine.trace_events()).unwrap_or_else(|e| fail(format!("{}: {}", trace, e)));
+    match result {
+        Ok(status) => eprintln!("{:?} after {} instructions", status, machine.trace_events().len()),
+        Err(e) => eprintln!("{}\nafter {} instructions", machine.describe_error(&e), machine.trace_events().len()),
+    }
+}
+
+fn diff(a: &str, b: &str, context: usize, map: Option<DebugInfo>) {
+    let (a, b) = (load_trace(a), load_trace(b));
+    match first_divergence(&a, &b) {
+        Some(index) => {
+            print!("{}", format_divergence(&a, &b, index, context));
+            if let Some(map) = map {
+                for (sign, trace) in [('-', &a), ('+', &b)] {
+                    if let Some(event) = trace.get(index) {
+                        println!("{} at {}", sign, map.describe_event(event));
+                    }
+                }
+            }
+            process::exit(1);
+        }
+        None => println!("traces are the same, {} events", a.len()),
+    }
+}
+
+fn heatmap(program: &str, trace: &str, image: &str, width: usize) {
+    let heatmap = Heatmap::from_trace(&load_program(program), &load_trace(trace));
+    fs::write(image, heatmap.to_ppm(width, 4)).unwrap_or_else(|e| fail(format!("{}: {}", image, e)));
+    eprintln!("{} cells, {} to a row", heatmap.counts.len(), width);
+    if heatmap.skipped > 0 {
+        eprintln!("skipped {} accesses outside of memory", heatmap.skipped);
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+    let map = match args.first().map(String::as_str) {
+        Some("--map") if args.len() >= 2 => Some(load_map(&args.drain(..2).nth(1).unwrap())),
+        Some("--map") => usage(),
+        _ => None,
+    };
+    match args.first().map(String::as_str) {
+        Some("record") if args.len() >= 3 => record(&args[1], &args[2], &args[3..], map),
+        Some("diff") if args.len() == 3 || args.len() == 4 => {
+            let context = match args.get(3) {
+                Some(context) => context.parse().unwrap_or_else(|_| usage()),
+                None => 3,
+            };
+            diff(&args[1], &args[2], context, map)
+        }
+        Some("heatmap") if map.is_none() && (args.len() == 4 || args.len() == 5) => {
+            let width = match args.get(4) {
+                Some(width) => width.parse().unwrap_or_else(|_| usage()),
+                None => 64,
+            };
+            heatmap(&args[1], &args[2], &args[3], width)
+        }
+        _ => usage(),
+    }
+}
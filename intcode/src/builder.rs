@@ -0,0 +1,366 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{channel, sync_channel, Receiver, SendError, Sender, SyncSender, TrySendError};
+
+use crate::debug::DebugInfo;
+use crate::{Machine, Word};
+
+/// What a machine does when it needs input and every input source is used up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputExhausted {
+    /// Return [`crate::Status::NeedsInput`]. Execution picks up at the same instruction once
+    /// more input is added.
+    Pause,
+    /// Stop with [`crate::Error::InputExhausted`].
+    Error,
+    /// Wait for a value to arrive through [`Machine::input_sender`].
+    #[cfg(feature = "std")]
+    Block,
+}
+
+/// What a machine does with output the receiver isn't keeping up with, or when the receiver is
+/// gone. Output is always recorded in [`Machine::get_output`] regardless.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputPolicy {
+    /// Wait for room in the channel. Output sent after the receiver is dropped is lost.
+    Block,
+    /// Throw away output that doesn't fit in the channel.
+    Drop,
+    /// Stop with [`crate::Error::OutputFull`] or [`crate::Error::OutputDisconnected`].
+    Error,
+    /// Buffer any amount of output; only a dropped receiver loses output.
+    Unbounded,
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub(crate) enum OutputSender<W> {
+    Bounded(SyncSender<W>),
+    Unbounded(Sender<W>),
+}
+
+#[cfg(feature = "std")]
+impl<W> OutputSender<W> {
+    pub(crate) fn send(&self, val: W) -> Result<(), SendError<W>> {
+        match self {
+            OutputSender::Bounded(tx) => tx.send(val),
+            OutputSender::Unbounded(tx) => tx.send(val),
+        }
+    }
+
+    pub(crate) fn try_send(&self, val: W) -> Result<(), TrySendError<W>> {
+        match self {
+            OutputSender::Bounded(tx) => tx.try_send(val),
+            OutputSender::Unbounded(tx) => tx.send(val).map_err(|e| TrySendError::Disconnected(e.0)),
+        }
+    }
+}
+
+/// Configures a [`Machine`]. `Machine::new` is the same as `MachineBuilder::new().build()`.
+///
+/// Machines hold `i64` cells unless [`MachineBuilder::word`] picks another [`Word`].
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use intcode::{InputExhausted, MachineBuilder, OutputPolicy};
+///
+/// let (rx, mut machine) = MachineBuilder::new()
+///     .memory_multiplier(4)
+///     .output_policy(OutputPolicy::Unbounded)
+///     .input_exhausted(InputExhausted::Error)
+///     .trace(false)
+///     .instruction_budget(1000)
+///     .build(vec![104, 42, 99]);
+/// machine.execute().unwrap();
+/// assert_eq!(rx.recv().unwrap(), 42);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MachineBuilder<W = i64> {
+    memory_multiplier: usize,
+    memory_limit: usize,
+    output_capacity: usize,
+    output_policy: OutputPolicy,
+    input_exhausted: InputExhausted,
+    trace: bool,
+    instruction_budget: Option<u64>,
+    checked_arithmetic: bool,
+    record_trace: bool,
+    debug_info: Option<DebugInfo>,
+    word: PhantomData<W>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        MachineBuilder {
+            // "available memory should be much larger than the initial program"
+            memory_multiplier: 1024,
+            // upper bound on size at 2^30 for memory reasons
+            memory_limit: 1 << 30,
+            output_capacity: 1024,
+            output_policy: OutputPolicy::Block,
+            input_exhausted: InputExhausted::Pause,
+            trace: true,
+            instruction_budget: None,
+            checked_arithmetic: false,
+            record_trace: false,
+            debug_info: None,
+            word: PhantomData,
+        }
+    }
+}
+
+impl MachineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<W: Word> MachineBuilder<W> {
+    /// Build machines with cells of type `V` instead.
+    ///
+    /// ```
+    /// use intcode::MachineBuilder;
+    ///
+    /// let mut machine = MachineBuilder::new().word::<i128>().trace(false).build_detached(vec![1102, 1 << 62, 8, 7, 4, 7, 99, 0]);
+    /// machine.execute().unwrap();
+    /// assert_eq!(machine.get_output(), &vec![1 << 65]);
+    /// ```
+    pub fn word<V: Word>(self) -> MachineBuilder<V> {
+        MachineBuilder {
+            memory_multiplier: self.memory_multiplier,
+            memory_limit: self.memory_limit,
+            output_capacity: self.output_capacity,
+            output_policy: self.output_policy,
+            input_exhausted: self.input_exhausted,
+            trace: self.trace,
+            instruction_budget: self.instruction_budget,
+            checked_arithmetic: self.checked_arithmetic,
+            record_trace: self.record_trace,
+            debug_info: self.debug_info,
+            word: PhantomData,
+        }
+    }
+
+    /// Memory is the program size times this, capped at [`MachineBuilder::memory_limit`].
+    pub fn memory_multiplier(mut self, multiplier: usize) -> Self {
+        self.memory_multiplier = multiplier;
+        self
+    }
+
+    /// Upper bound on the number of memory cells. The program itself is always loaded in full.
+    pub fn memory_limit(mut self, cells: usize) -> Self {
+        self.memory_limit = cells;
+        self
+    }
+
+    /// Number of outputs that can be waiting in the channel before the output policy kicks in.
+    /// Ignored for [`OutputPolicy::Unbounded`].
+    pub fn output_capacity(mut self, capacity: usize) -> Self {
+        self.output_capacity = capacity;
+        self
+    }
+
+    pub fn output_policy(mut self, policy: OutputPolicy) -> Self {
+        self.output_policy = policy;
+        self
+    }
+
+    pub fn input_exhausted(mut self, behaviour: InputExhausted) -> Self {
+        self.input_exhausted = behaviour;
+        self
+    }
+
+    /// Print every instruction, load and store to stdout.
+    pub fn trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled;
+        self
+    }
+
+    /// Stop with [`crate::Error::BudgetExhausted`] after this many instructions.
+    pub fn instruction_budget(mut self, instructions: u64) -> Self {
+        self.instruction_budget = Some(instructions);
+        self
+    }
+
+    /// Stop with [`crate::Error::Overflow`] when an addition or multiplication doesn't fit in a
+    /// cell. By default results wrap around.
+    pub fn checked_arithmetic(mut self, enabled: bool) -> Self {
+        self.checked_arithmetic = enabled;
+        self
+    }
+
+    /// Keep a [`crate::TraceEvent`] for every instruction executed, see
+    /// [`Machine::trace_events`].
+    pub fn record_trace(mut self, enabled: bool) -> Self {
+        self.record_trace = enabled;
+        self
+    }
+
+    /// Show the label and source line of every instruction in the trace, see [`crate::debug`].
+    /// [`Machine::describe_error`] puts them to errors as well.
+    pub fn debug_info(mut self, debug: DebugInfo) -> Self {
+        self.debug_info = Some(debug);
+        self
+    }
+
+    /// Build a machine and the receiving end of its output channel.
+    #[cfg(feature = "std")]
+    pub fn build(self, program: Vec<W>) -> (Receiver<W>, Machine<W>) {
+        let (tx, rx) = match self.output_policy {
+            OutputPolicy::Unbounded => {
+                let (tx, rx) = channel();
+                (OutputSender::Unbounded(tx), rx)
+            }
+            _ => {
+                let (tx, rx) = sync_channel(self.output_capacity);
+                (OutputSender::Bounded(tx), rx)
+            }
+        };
+        let mut machine = self.build_detached(program);
+        machine.output_tx = Some(tx);
+        (rx, machine)
+    }
+
+    // memory for a program of `len` words
+    pub(crate) fn memory_size(&self, len: usize) -> usize {
+        len.saturating_mul(self.memory_multiplier).min(self.memory_limit).max(len)
+    }
+
+    /// Build a machine without an output channel; output is only kept in
+    /// [`Machine::get_output`]. This is how machines are built without the `std` feature.
+    pub fn build_detached(self, program: Vec<W>) -> Machine<W> {
+        let size = self.memory_size(program.len());
+        let mut memory = program;
+        memory.resize(size, W::from_i64(0));
+
+        Machine {
+            memory,
+            program_counter: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+            #[cfg(feature = "std")]
+            output_tx: None,
+            output: vec![],
+            #[cfg(feature = "std")]
+            output_policy: self.output_policy,
+            outputs_lost: 0,
+            detect_self_modification: false,
+            executed: BTreeMap::new(),
+            self_modifications: vec![],
+            code_modified: false,
+            extensions: BTreeMap::new(),
+            lenient_modes: false,
+            checked_arithmetic: self.checked_arithmetic,
+            input_exhausted: self.input_exhausted,
+            #[cfg(feature = "std")]
+            blocking_input: match self.input_exhausted {
+                InputExhausted::Block => Some(channel()),
+                _ => None,
+            },
+            trace: self.trace,
+            instruction_budget: self.instruction_budget,
+            instructions_executed: 0,
+            trace_events: if self.record_trace { Some(vec![]) } else { None },
+            current_event: None,
+            debug_info: self.debug_info,
+            compiled: vec![],
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Error, InputType, Status};
+
+    #[test]
+    fn it_sizes_memory() {
+        let (_, m) = MachineBuilder::new().build(vec![99, 0, 0]);
+        assert_eq!(m.memory.len(), 3 * 1024);
+
+        let (_, m) = MachineBuilder::new().memory_multiplier(4).build(vec![99, 0, 0]);
+        assert_eq!(m.memory.len(), 12);
+
+        let (_, m) = MachineBuilder::new().memory_limit(2).build(vec![99, 0, 0]);
+        assert_eq!(m.memory.len(), 3);
+    }
+
+    #[test]
+    fn it_buffers_unbounded_output() {
+        // outputs 0..2000, more than the default channel capacity, with nobody reading
+        let program = vec![1101, 0, 0, 18, 4, 18, 1001, 18, 1, 18, 1007, 18, 2000, 19, 1005, 19, 4, 99, 0, 0];
+        let (rx, mut m) = MachineBuilder::new()
+            .output_policy(OutputPolicy::Unbounded)
+            .trace(false)
+            .build(program);
+        m.execute().unwrap();
+        assert_eq!(rx.iter().count(), 2000);
+    }
+
+    #[test]
+    fn it_applies_the_output_policy() {
+        let outputs = vec![104, 1, 104, 2, 104, 3, 99];
+
+        let (rx, mut m) = MachineBuilder::new()
+            .output_capacity(1)
+            .output_policy(OutputPolicy::Drop)
+            .build(outputs.clone());
+        m.execute().unwrap();
+        assert_eq!(m.outputs_lost(), 2);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(m.get_output(), &vec![1, 2, 3]);
+
+        let (_rx, mut m) = MachineBuilder::new()
+            .output_capacity(1)
+            .output_policy(OutputPolicy::Error)
+            .build(outputs.clone());
+        assert_eq!(m.execute(), Err(Error::OutputFull { pc: 2 }));
+
+        let (rx, mut m) = MachineBuilder::new().output_policy(OutputPolicy::Error).build(outputs.clone());
+        drop(rx);
+        assert_eq!(m.execute(), Err(Error::OutputDisconnected { pc: 0 }));
+
+        let (rx, mut m) = MachineBuilder::new().build(outputs);
+        drop(rx);
+        m.execute().unwrap();
+        assert_eq!(m.outputs_lost(), 3);
+    }
+
+    #[test]
+    fn it_can_fail_or_block_on_missing_input() {
+        let (_, mut m) = MachineBuilder::new().input_exhausted(InputExhausted::Error).build(vec![3, 0, 99]);
+        assert_eq!(m.execute(), Err(Error::InputExhausted { pc: 0 }));
+
+        let (_, m) = MachineBuilder::new().input_exhausted(InputExhausted::Block).build(vec![3, 0, 99]);
+        let tx = m.input_sender().unwrap();
+        let handle = m.execute_async();
+        tx.send(7).unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(Status::Halted));
+
+        let mut m = MachineBuilder::new().input_exhausted(InputExhausted::Block).build_detached(vec![3, 0, 4, 0, 99]);
+        m.input_sender().unwrap().send(8).unwrap();
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![8]);
+    }
+
+    #[test]
+    fn it_builds_machines_without_an_output_channel() {
+        let mut m = MachineBuilder::new().trace(false).build_detached(vec![3, 9, 102, 2, 9, 9, 4, 9, 99, 0]);
+        m.set_input(InputType::Values(vec![21].into()));
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![42]);
+        assert_eq!(m.outputs_lost(), 0);
+    }
+
+    #[test]
+    fn it_enforces_the_instruction_budget() {
+        // jumps to itself forever
+        let (_, mut m) = MachineBuilder::new().trace(false).instruction_budget(100).build(vec![1105, 1, 0]);
+        assert_eq!(m.execute(), Err(Error::BudgetExhausted { pc: 0 }));
+        assert_eq!(m.instructions_executed(), 100);
+    }
+}
@@ -0,0 +1,263 @@
+//! Static control-flow analysis of intcode programs.
+//!
+//! [`Cfg::build`] follows execution from address 0 without running anything: every reachable
+//! instruction is decoded, jumps (opcodes 5 and 6) with an immediate-mode target become edges,
+//! and the program is split into basic blocks. Jumps whose target comes from memory can't be
+//! resolved statically and are flagged instead. [`Cfg::to_dot`] renders the result for Graphviz.
+//!
+//! The analysis assumes the program does not modify its own instructions.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::{decode, instruction_length, Instruction};
+
+/// How control leaves a basic block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Runs into the next block.
+    FallThrough,
+    /// Ends with opcode 99.
+    Halt,
+    /// Ends with a jump that is always taken.
+    Jump,
+    /// Ends with a jump that may or may not be taken.
+    Branch,
+    /// Ends with a jump whose target is read from memory.
+    IndirectJump,
+    /// Runs into something that does not decode as an instruction.
+    Invalid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Address of the first instruction.
+    pub start: usize,
+    /// Addresses of every instruction in the block, in order.
+    pub instructions: Vec<usize>,
+    pub exit: Exit,
+    /// Blocks control may continue to. For branches the taken target comes first.
+    pub successors: Vec<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cfg {
+    /// Blocks keyed by their start address.
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    /// Addresses of jumps whose target could not be resolved.
+    pub indirect_jumps: Vec<usize>,
+}
+
+// What a single decoded instruction does to control flow.
+struct Flow {
+    exit: Option<Exit>,
+    successors: Vec<usize>,
+}
+
+fn instruction_at(program: &[i64], addr: usize) -> Option<(Instruction, usize)> {
+    let instr = decode(*program.get(addr)?)?;
+    let len = instruction_length(instr.opcode)?;
+    if instr.mode_op1 > 2 || instr.mode_op2 > 2 || instr.mode_op3 > 2 || addr + len > program.len() {
+        return None;
+    }
+    Some((instr, len))
+}
+
+fn flow(program: &[i64], addr: usize) -> Flow {
+    let (instr, len) = match instruction_at(program, addr) {
+        Some(decoded) => decoded,
+        None => return Flow { exit: Some(Exit::Invalid), successors: vec![] },
+    };
+    let next = addr + len;
+    match instr.opcode {
+        99 => Flow { exit: Some(Exit::Halt), successors: vec![] },
+        5 | 6 => {
+            let taken = if instr.mode_op1 == 1 {
+                Some((program[addr + 1] != 0) == (instr.opcode == 5))
+            } else {
+                None // decided at runtime
+            };
+            let target = if instr.mode_op2 == 1 && program[addr + 2] >= 0 {
+                Some(program[addr + 2] as usize)
+            } else {
+                None
+            };
+            match (taken, target) {
+                (Some(false), _) => Flow { exit: None, successors: vec![next] },
+                (Some(true), Some(target)) => Flow { exit: Some(Exit::Jump), successors: vec![target] },
+                (Some(true), None) => Flow { exit: Some(Exit::IndirectJump), successors: vec![] },
+                (None, Some(target)) => Flow { exit: Some(Exit::Branch), successors: vec![target, next] },
+                (None, None) => Flow { exit: Some(Exit::IndirectJump), successors: vec![next] },
+            }
+        }
+        _ => Flow { exit: None, successors: vec![next] },
+    }
+}
+
+impl Cfg {
+    pub fn build(program: &[i64]) -> Cfg {
+        Cfg::build_from(program, &[0])
+    }
+
+    /// Like [`Cfg::build`], but following execution from each of `entries`. Useful for code that
+    /// is only reached through computed jumps, like the instruction after a call.
+    pub fn build_from(program: &[i64], entries: &[usize]) -> Cfg {
+        // find every reachable instruction and where blocks have to start
+        let mut leaders = entries.iter().cloned().collect::<BTreeSet<_>>();
+        let mut seen = BTreeSet::new();
+        let mut work = entries.to_vec();
+        while let Some(addr) = work.pop() {
+            if !seen.insert(addr) {
+                continue;
+            }
+            let flow = flow(program, addr);
+            if flow.exit.is_some() {
+                leaders.extend(flow.successors.iter().cloned());
+            }
+            work.extend(flow.successors);
+        }
+
+        let mut cfg = Cfg::default();
+        for &start in &leaders {
+            let mut block = BasicBlock { start, instructions: vec![], exit: Exit::FallThrough, successors: vec![] };
+            let mut addr = start;
+            loop {
+                block.instructions.push(addr);
+                let flow = flow(program, addr);
+                if let Some(exit) = flow.exit {
+                    block.exit = exit;
+                    block.successors = flow.successors;
+                    if exit == Exit::IndirectJump {
+                        cfg.indirect_jumps.push(addr);
+                    }
+                    break;
+                }
+                addr = flow.successors[0];
+                if leaders.contains(&addr) {
+                    block.successors = vec![addr];
+                    break;
+                }
+            }
+            cfg.blocks.insert(start, block);
+        }
+        cfg
+    }
+
+    /// Render the graph in Graphviz DOT format, one node per basic block.
+    pub fn to_dot(&self, program: &[i64]) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for &addr in &block.instructions {
+                write!(label, "{}\\l", format_instruction(program, addr)).unwrap();
+            }
+            let style = match block.exit {
+                Exit::IndirectJump => ", color=red",
+                Exit::Invalid => ", style=dashed",
+                _ => "",
+            };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+            for (i, succ) in block.successors.iter().enumerate() {
+                let edge_label = match (block.exit, i) {
+                    (Exit::Branch, 0) => " [label=\"taken\"]",
+                    (Exit::Branch, _) => " [label=\"not taken\"]",
+                    _ => "",
+                };
+                writeln!(dot, "    b{} -> b{}{};", block.start, succ, edge_label).unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+/// Human readable name of an opcode.
+pub fn mnemonic(opcode: i32) -> Option<&'static str> {
+    match opcode {
+        1 => Some("ADD"),
+        2 => Some("MULT"),
+        3 => Some("IN"),
+        4 => Some("OUT"),
+        5 => Some("JT"),
+        6 => Some("JF"),
+        7 => Some("LT"),
+        8 => Some("EQ"),
+        9 => Some("ARB"),
+        99 => Some("HALT"),
+        _ => None,
+    }
+}
+
+/// Disassemble the instruction at `addr`, e.g. `8: ADD [9] #3 [rb+1]`. Position-mode operands are
+/// shown as `[addr]`, immediate-mode operands as `#value` and relative-mode operands as
+/// `[rb+offset]`. Anything that doesn't decode is shown as raw data.
+pub fn format_instruction(program: &[i64], addr: usize) -> String {
+    let (instr, len) = match instruction_at(program, addr) {
+        Some(decoded) => decoded,
+        None => return format!("{}: DATA {}", addr, program.get(addr).cloned().unwrap_or(0)),
+    };
+    let mut text = format!("{}: {}", addr, mnemonic(instr.opcode).unwrap());
+    let modes = [instr.mode_op1, instr.mode_op2, instr.mode_op3];
+    for (i, mode) in modes.iter().enumerate().take(len - 1) {
+        let param = program[addr + 1 + i];
+        match mode {
+            1 => write!(text, " #{}", param).unwrap(),
+            2 if param < 0 => write!(text, " [rb{}]", param).unwrap(),
+            2 => write!(text, " [rb+{}]", param).unwrap(),
+            _ => write!(text, " [{}]", param).unwrap(),
+        }
+    }
+    text
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // day 5 example: outputs 0 if the input was zero, otherwise 1
+    const IS_ZERO: [i64; 16] = [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+
+    #[test]
+    fn it_splits_straight_line_code_into_one_block() {
+        let cfg = Cfg::build(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[&0].instructions, vec![0, 4, 8]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Halt);
+        assert_eq!(format_instruction(&[21201, -1, 3, 2], 0), "0: ADD [rb-1] #3 [rb+2]");
+    }
+
+    #[test]
+    fn it_flags_indirect_jumps() {
+        let cfg = Cfg::build(&IS_ZERO);
+        // the jump target lives in memory at address 15
+        assert_eq!(cfg.indirect_jumps, vec![2]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::IndirectJump);
+        assert_eq!(cfg.blocks[&0].successors, vec![5]);
+        assert_eq!(cfg.blocks[&5].instructions, vec![5, 9, 11]);
+    }
+
+    #[test]
+    fn it_resolves_immediate_jumps() {
+        // outputs 1 if the input is non-zero, otherwise 0
+        let program = [3, 12, 1005, 12, 9, 1101, 0, 0, 13, 4, 13, 99, 0, 1];
+        let cfg = Cfg::build(&program);
+        assert_eq!(cfg.blocks.keys().cloned().collect::<Vec<_>>(), vec![0, 5, 9]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Branch);
+        assert_eq!(cfg.blocks[&0].successors, vec![9, 5]);
+        assert_eq!(cfg.blocks[&5].exit, Exit::FallThrough);
+        assert_eq!(cfg.blocks[&5].successors, vec![9]);
+        assert!(cfg.indirect_jumps.is_empty());
+
+        let dot = cfg.to_dot(&program);
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("b0 -> b9 [label=\"taken\"];"));
+        assert!(dot.contains("b0 -> b5 [label=\"not taken\"];"));
+        assert!(dot.contains("2: JT [12] #9\\l"));
+    }
+}
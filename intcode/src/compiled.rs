@@ -0,0 +1,254 @@
+//! Pre-decoded execution.
+//!
+//! [`Machine::execute`] decodes the instruction at the program counter on every step.
+//! [`Machine::execute_compiled`] decodes each instruction once into a compact [`Op`] whose
+//! operands are plain memory addresses and keeps it until a write lands on one of the
+//! instruction's cells. Anything the compact form doesn't cover (registered opcodes, bad modes,
+//! relative-mode operands, addresses outside of memory) is handed to [`Machine::step`], so both
+//! behave the same.
+
+use alloc::vec;
+use core::convert::TryFrom;
+
+use crate::extension::Param;
+use crate::{builtin_params, decode, Error, Machine, Status, Word};
+
+// Every operand is the address of the cell holding its value: the parameter itself in immediate
+// mode, the cell it points at in position mode. Write operands are the address written to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Op {
+    Add(usize, usize, usize),
+    Mul(usize, usize, usize),
+    Input(usize),
+    Output(usize),
+    JumpIfTrue(usize, usize),
+    JumpIfFalse(usize, usize),
+    LessThan(usize, usize, usize),
+    Equals(usize, usize, usize),
+    AdjustBase(usize),
+    Halt,
+}
+
+// longest built-in instruction
+const MAX_LEN: usize = 4;
+
+impl Op {
+    fn len(&self) -> usize {
+        match self {
+            Op::Halt => 1,
+            Op::Input(_) | Op::Output(_) | Op::AdjustBase(_) => 2,
+            Op::JumpIfTrue(..) | Op::JumpIfFalse(..) => 3,
+            Op::Add(..) | Op::Mul(..) | Op::LessThan(..) | Op::Equals(..) => 4,
+        }
+    }
+}
+
+impl<W: Word> Machine<W> {
+    /// Run until the program halts or needs input, like [`Machine::execute`], but decode each
+    /// instruction only once. Tracing, trace recording and self-modification detection need the
+    /// interpreter, so with any of them turned on this is the same as `execute`.
+    pub fn execute_compiled(&mut self) -> Result<Status, Error> {
+        if self.trace || self.trace_events.is_some() || self.detect_self_modification {
+            return self.execute();
+        }
+        if self.compiled.len() != self.memory.len() {
+            self.compiled = vec![None; self.memory.len()];
+        }
+        loop {
+            if self.instruction_budget.is_some_and(|budget| self.instructions_executed >= budget) {
+                return Err(Error::BudgetExhausted { pc: self.program_counter });
+            }
+            let pc = self.program_counter;
+            let op = match self.compiled.get(pc as usize) {
+                Some(Some(op)) => *op,
+                _ => match self.compile(pc) {
+                    Some(op) => {
+                        self.compiled[pc as usize] = Some(op);
+                        op
+                    }
+                    None => {
+                        if let Some(status) = self.step()? {
+                            return Ok(status);
+                        }
+                        continue;
+                    }
+                },
+            };
+            self.instructions_executed += 1;
+            let mut next = pc + op.len() as i64;
+            match op {
+                Op::Add(a, b, out) => {
+                    let sum = self.add(&self.memory[a], &self.memory[b])?;
+                    self.store(out as i64, sum)?;
+                }
+                Op::Mul(a, b, out) => {
+                    let product = self.mul(&self.memory[a], &self.memory[b])?;
+                    self.store(out as i64, product)?;
+                }
+                Op::Input(out) => match self.read_input()? {
+                    Some(val) => self.store(out as i64, val)?,
+                    None => return Ok(self.pause_for_input()),
+                },
+                Op::Output(a) => self.emit_output(self.memory[a].clone())?,
+                Op::JumpIfTrue(a, target) => {
+                    if !self.memory[a].is_zero() {
+                        next = self.memory[target].to_i64_saturating();
+                    }
+                }
+                Op::JumpIfFalse(a, target) => {
+                    if self.memory[a].is_zero() {
+                        next = self.memory[target].to_i64_saturating();
+                    }
+                }
+                Op::LessThan(a, b, out) => {
+                    let val = W::from_i64((self.memory[a] < self.memory[b]) as i64);
+                    self.store(out as i64, val)?;
+                }
+                Op::Equals(a, b, out) => {
+                    let val = W::from_i64((self.memory[a] == self.memory[b]) as i64);
+                    self.store(out as i64, val)?;
+                }
+                Op::AdjustBase(a) => {
+                    self.relative_base = self.relative_base.saturating_add(self.memory[a].to_i64_saturating());
+                }
+                Op::Halt => {
+                    self.halt();
+                    return Ok(Status::Halted);
+                }
+            }
+            self.program_counter = next;
+        }
+    }
+
+    // None for anything the interpreter should deal with instead
+    pub(crate) fn compile(&self, pc: i64) -> Option<Op> {
+        let pc = usize::try_from(pc).ok()?;
+        let instr = decode(self.memory.get(pc)?.to_i64()?)?;
+        if self.extensions.contains_key(&instr.opcode) {
+            return None;
+        }
+        let modes = [instr.mode_op1, instr.mode_op2, instr.mode_op3];
+        let mut operands = [0; MAX_LEN - 1];
+        for (i, param) in builtin_params(instr.opcode)?.iter().enumerate() {
+            let cell = pc + 1 + i;
+            let mode = match param {
+                Param::Write if self.lenient_modes && modes[i] != 2 => 0,
+                _ => modes[i],
+            };
+            operands[i] = match (param, mode) {
+                (Param::Read, 1) if cell < self.memory.len() => cell,
+                (_, 0) => {
+                    let addr = usize::try_from(self.memory.get(cell)?.to_i64()?).ok()?;
+                    if addr >= self.memory.len() {
+                        return None;
+                    }
+                    addr
+                }
+                _ => return None,
+            };
+        }
+        let [a, b, c] = operands;
+        Some(match instr.opcode {
+            1 => Op::Add(a, b, c),
+            2 => Op::Mul(a, b, c),
+            3 => Op::Input(a),
+            4 => Op::Output(a),
+            5 => Op::JumpIfTrue(a, b),
+            6 => Op::JumpIfFalse(a, b),
+            7 => Op::LessThan(a, b, c),
+            8 => Op::Equals(a, b, c),
+            9 => Op::AdjustBase(a),
+            99 => Op::Halt,
+            _ => return None,
+        })
+    }
+
+    // a write to `addr` makes every cached instruction covering it stale
+    pub(crate) fn invalidate_compiled(&mut self, addr: usize) {
+        if addr >= self.compiled.len() {
+            return;
+        }
+        for start in addr.saturating_sub(MAX_LEN - 1)..=addr {
+            if self.compiled[start].is_some_and(|op| start + op.len() > addr) {
+                self.compiled[start] = None;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::extension::{Control, Param};
+    use crate::{Error, MachineBuilder, Status};
+
+    // the result, output and the program's memory afterwards
+    fn run(program: &[i64], input: &str, compiled: bool) -> (Result<Status, Error>, Vec<i64>, Vec<i64>) {
+        let (_, mut m) = MachineBuilder::new().trace(false).instruction_budget(10_000).build(program.to_vec());
+        m.set_input_string(input.to_string());
+        let result = if compiled { m.execute_compiled() } else { m.execute() };
+        (result, m.get_output().clone(), m.read_range(0..program.len()).unwrap().to_vec())
+    }
+
+    #[test]
+    fn it_matches_the_interpreter() {
+        let programs: &[(&[i64], &str)] = &[
+            (&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], ""),
+            (&[1, 1, 1, 4, 99, 5, 6, 0, 99], ""),
+            // day 5: is the input equal to 8, less than 8, is it zero
+            (&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], "8\n"),
+            (&[3, 3, 1107, -1, 8, 3, 4, 3, 99], "5\n"),
+            (&[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9], "0\n"),
+            // day 9 quine, relative operands are interpreted
+            (&[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99], ""),
+            // errors come from the interpreter
+            (&[1101, 1, 1, 5, 304, 0, 99], ""),
+            (&[11101, 1, 1, 0, 99], ""),
+            (&[3, 0, 99], ""),
+        ];
+        for (program, input) in programs {
+            assert_eq!(run(program, input, true), run(program, input, false), "{:?}", program);
+        }
+    }
+
+    #[test]
+    fn it_recompiles_modified_instructions() {
+        // the ADD at 0 stores its first operand, which the ADD at 4 increments until it hits 5
+        let program = [1101, 0, 0, 19, 1001, 1, 1, 1, 1007, 1, 5, 18, 1005, 18, 0, 4, 19, 99, 0, 0];
+        assert_eq!(run(&program, "", true), run(&program, "", false));
+        assert_eq!(run(&program, "", true).1, vec![4]);
+
+        // restoring a snapshot replaces the code the cached instructions came from
+        let (_, mut m) = MachineBuilder::new().trace(false).build(vec![104, 1, 99, 0]);
+        m.execute_compiled().unwrap();
+        let snapshot = crate::Snapshot { memory: vec![4, 3, 99, 7], memory_size: 4, program_counter: 0, relative_base: 0, output: vec![], instructions_executed: 0 };
+        m.restore(&snapshot);
+        m.execute_compiled().unwrap();
+        assert_eq!(m.get_output(), &vec![7]);
+    }
+
+    #[test]
+    fn it_resumes_and_runs_custom_instructions() {
+        // out = in0 + in1, with opcode 10 doubling the sum first
+        let (_, mut m) = MachineBuilder::new().trace(false).build(vec![3, 13, 3, 14, 1, 13, 14, 15, 10, 15, 4, 15, 99, 0, 0, 0]);
+        m.register_opcode(10, &[Param::Write], |ctx, ops| {
+            let val = ctx.load(ops[0])?;
+            ctx.store(ops[0], val * 2)?;
+            Ok(Control::Continue)
+        })
+        .unwrap();
+        m.set_input_string("20\n".to_string());
+        assert_eq!(m.execute_compiled(), Ok(Status::NeedsInput));
+        assert_eq!(m.instructions_executed(), 1);
+        m.set_input_string("1\n".to_string());
+        assert_eq!(m.execute_compiled(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![42]);
+        assert_eq!(m.instructions_executed(), 6);
+    }
+
+    #[test]
+    fn it_enforces_the_instruction_budget() {
+        let (_, mut m) = MachineBuilder::new().trace(false).instruction_budget(100).build(vec![1105, 1, 0]);
+        assert_eq!(m.execute_compiled(), Err(Error::BudgetExhausted { pc: 0 }));
+        assert_eq!(m.instructions_executed(), 100);
+    }
+}
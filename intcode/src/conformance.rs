@@ -0,0 +1,383 @@
+//! A conformance suite any intcode implementation can be checked against.
+//!
+//! [`CASES`] holds the example programs from the puzzles: day 2's arithmetic, day 5's
+//! parameter modes, comparisons and jumps, and day 9's quine and large numbers. A few smaller
+//! programs for relative mode and memory past the program, labeled "extra", aren't from the
+//! puzzles. Each case has its input and the output it should produce, and for some the memory
+//! it should finish with. Anything that can run a program implements [`Engine`], and [`check`]
+//! runs every case on it:
+//!
+//! ```
+//! use intcode::conformance::{check, Interpreter};
+//!
+//! assert!(check(&Interpreter).is_empty());
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use crate::{Error, InputExhausted, InputType, MachineBuilder};
+
+/// A way of running intcode programs.
+pub trait Engine {
+    /// Run `program` to a halt, with `input` as the only input.
+    fn run(&self, program: &[i64], input: &[i64]) -> Result<Run, Error>;
+}
+
+/// What a program did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Run {
+    pub output: Vec<i64>,
+    /// At least as long as the program; anything past it isn't checked.
+    pub memory: Vec<i64>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Case {
+    pub name: &'static str,
+    pub program: &'static [i64],
+    pub input: &'static [i64],
+    pub output: &'static [i64],
+    /// How the program's memory should end up, if it's part of the example.
+    pub memory: Option<&'static [i64]>,
+}
+
+const fn memory(name: &'static str, program: &'static [i64], memory: &'static [i64]) -> Case {
+    Case { name, program, input: &[], output: &[], memory: Some(memory) }
+}
+
+const fn io(name: &'static str, program: &'static [i64], input: &'static [i64], output: &'static [i64]) -> Case {
+    Case { name, program, input, output, memory: None }
+}
+
+const EQUAL_POSITION: &[i64] = &[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+const LESS_POSITION: &[i64] = &[3, 9, 7, 9, 10, 9, 4, 9, 99, -1, 8];
+const EQUAL_IMMEDIATE: &[i64] = &[3, 3, 1108, -1, 8, 3, 4, 3, 99];
+const LESS_IMMEDIATE: &[i64] = &[3, 3, 1107, -1, 8, 3, 4, 3, 99];
+const JUMP_POSITION: &[i64] = &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9];
+const JUMP_IMMEDIATE: &[i64] = &[3, 3, 1105, -1, 9, 1101, 0, 0, 12, 4, 12, 99, 1];
+#[rustfmt::skip]
+const COMPARE_TO_8: &[i64] = &[
+    3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21,
+    125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99,
+];
+const QUINE: &[i64] = &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+
+#[rustfmt::skip]
+pub const CASES: &[Case] = &[
+    memory("day 2: add and multiply", &[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50], &[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]),
+    memory("day 2: 1 + 1", &[1, 0, 0, 0, 99], &[2, 0, 0, 0, 99]),
+    memory("day 2: 3 * 2", &[2, 3, 0, 3, 99], &[2, 3, 0, 6, 99]),
+    memory("day 2: 99 * 99", &[2, 4, 4, 5, 99, 0], &[2, 4, 4, 5, 99, 9801]),
+    memory("day 2: overwritten halt", &[1, 1, 1, 4, 99, 5, 6, 0, 99], &[30, 1, 1, 4, 2, 5, 6, 0, 99]),
+    io("day 5: echo", &[3, 0, 4, 0, 99], &[42], &[42]),
+    memory("day 5: immediate mode", &[1002, 4, 3, 4, 33], &[1002, 4, 3, 4, 99]),
+    memory("day 5: negative numbers", &[1101, 100, -1, 4, 0], &[1101, 100, -1, 4, 99]),
+    io("day 5: equal to 8, position mode", EQUAL_POSITION, &[8], &[1]),
+    io("day 5: not equal to 8, position mode", EQUAL_POSITION, &[7], &[0]),
+    io("day 5: less than 8, position mode", LESS_POSITION, &[5], &[1]),
+    io("day 5: not less than 8, position mode", LESS_POSITION, &[8], &[0]),
+    io("day 5: equal to 8, immediate mode", EQUAL_IMMEDIATE, &[8], &[1]),
+    io("day 5: not equal to 8, immediate mode", EQUAL_IMMEDIATE, &[9], &[0]),
+    io("day 5: less than 8, immediate mode", LESS_IMMEDIATE, &[7], &[1]),
+    io("day 5: not less than 8, immediate mode", LESS_IMMEDIATE, &[8], &[0]),
+    io("day 5: jump on zero, position mode", JUMP_POSITION, &[0], &[0]),
+    io("day 5: jump on non-zero, position mode", JUMP_POSITION, &[5], &[1]),
+    io("day 5: jump on zero, immediate mode", JUMP_IMMEDIATE, &[0], &[0]),
+    io("day 5: jump on non-zero, immediate mode", JUMP_IMMEDIATE, &[3], &[1]),
+    io("day 5: below 8", COMPARE_TO_8, &[7], &[999]),
+    io("day 5: 8", COMPARE_TO_8, &[8], &[1000]),
+    io("day 5: above 8", COMPARE_TO_8, &[9], &[1001]),
+    io("day 9: quine", QUINE, &[], QUINE),
+    io("day 9: 16 digit number", &[1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[], &[1219070632396864]),
+    io("day 9: large number", &[104, 1125899906842624, 99], &[], &[1125899906842624]),
+    io("extra: relative read", &[109, -1, 204, 1, 99], &[], &[109]),
+    io("extra: relative base from memory", &[109, 1, 9, 2, 204, -6, 99], &[], &[204]),
+    io("extra: relative base from relative mode", &[109, 1, 209, -1, 204, -106, 99], &[], &[204]),
+    io("extra: relative input", &[109, 1, 203, 2, 204, 2, 99], &[7], &[7]),
+    io("extra: memory past the program", &[1101, 2, 3, 1000, 4, 1000, 99], &[], &[5]),
+];
+
+/// A case an engine got wrong.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub case: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.case, self.message)
+    }
+}
+
+/// Run every case in [`CASES`] on `engine`. Empty if it passes them all.
+pub fn check<E: Engine + ?Sized>(engine: &E) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for case in CASES {
+        let message = match engine.run(case.program, case.input) {
+            Err(err) => format!("{}", err),
+            Ok(run) if run.output != case.output => format!("output {:?}, expected {:?}", run.output, case.output),
+            Ok(run) => match case.memory {
+                Some(memory) if run.memory.get(..memory.len()) != Some(memory) => {
+                    format!("memory {:?}, expected {:?}", &run.memory[..memory.len().min(run.memory.len())], memory)
+                }
+                _ => continue,
+            },
+        };
+        failures.push(Failure { case: case.name, message });
+    }
+    failures
+}
+
+/// [`crate::Machine::execute`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Interpreter;
+
+/// [`crate::Machine::execute_compiled`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Compiled;
+
+fn run_machine(builder: MachineBuilder, program: &[i64], input: &[i64], compiled: bool) -> Result<Run, Error> {
+    let mut machine = builder
+        .trace(false)
+        .input_exhausted(InputExhausted::Error)
+        .build_detached(program.to_vec());
+    machine.set_input(InputType::Values(input.iter().copied().collect()));
+    if compiled {
+        machine.execute_compiled()?;
+    } else {
+        machine.execute()?;
+    }
+    let memory = machine.read_range(0..machine.memory_size()).unwrap_or(&[]).to_vec();
+    Ok(Run { output: machine.get_output().clone(), memory })
+}
+
+impl Engine for Interpreter {
+    fn run(&self, program: &[i64], input: &[i64]) -> Result<Run, Error> {
+        run_machine(MachineBuilder::new(), program, input, false)
+    }
+}
+
+impl Engine for Compiled {
+    fn run(&self, program: &[i64], input: &[i64]) -> Result<Run, Error> {
+        run_machine(MachineBuilder::new(), program, input, true)
+    }
+}
+
+/// A second implementation written plainly from the puzzle text, sharing no code with
+/// [`crate::Machine`], to test it against. Where the puzzles leave things open it makes the
+/// same documented choices: arithmetic wraps, relative addresses saturate, words outside
+/// `0..100000` aren't instructions and mode digits past the last parameter are ignored.
+#[derive(Clone, Copy, Debug)]
+pub struct Reference {
+    /// Stop with [`Error::BudgetExhausted`] after this many instructions.
+    pub budget: Option<u64>,
+    /// Memory is the program size times this, like [`MachineBuilder::memory_multiplier`].
+    pub memory_multiplier: usize,
+}
+
+impl Default for Reference {
+    fn default() -> Self {
+        Reference { budget: None, memory_multiplier: 1024 }
+    }
+}
+
+struct State {
+    memory: Vec<i64>,
+    pc: i64,
+    base: i64,
+    word: i64,
+}
+
+impl State {
+    fn read(&self, addr: i64) -> Result<i64, Error> {
+        usize::try_from(addr)
+            .ok()
+            .and_then(|i| self.memory.get(i))
+            .copied()
+            .ok_or(Error::InvalidAddress { pc: self.pc, addr })
+    }
+
+    fn write(&mut self, addr: i64, val: i64) -> Result<(), Error> {
+        let pc = self.pc;
+        let cell = usize::try_from(addr).ok().and_then(|i| self.memory.get_mut(i));
+        *cell.ok_or(Error::InvalidAddress { pc, addr })? = val;
+        Ok(())
+    }
+
+    fn mode(&self, param: u32) -> i64 {
+        self.word / 10i64.pow(param + 1) % 10
+    }
+
+    // the address parameter `param` (from 1) refers to
+    fn addr(&self, param: u32) -> Result<i64, Error> {
+        let cell = self.pc + param as i64;
+        match self.mode(param) {
+            1 => Ok(cell),
+            2 => Ok(self.base.saturating_add(self.read(cell)?)),
+            _ => self.read(cell),
+        }
+    }
+
+    fn value(&self, param: u32) -> Result<i64, Error> {
+        self.read(self.addr(param)?)
+    }
+}
+
+impl Engine for Reference {
+    fn run(&self, program: &[i64], input: &[i64]) -> Result<Run, Error> {
+        let size = program.len().saturating_mul(self.memory_multiplier).min(1 << 30).max(program.len());
+        let mut memory = program.to_vec();
+        memory.resize(size, 0);
+        let mut state = State { memory, pc: 0, base: 0, word: 0 };
+        let mut input = input.iter();
+        let mut output = Vec::new();
+        let mut steps = 0;
+        loop {
+            let pc = state.pc;
+            if self.budget.is_some_and(|budget| steps >= budget) {
+                return Err(Error::BudgetExhausted { pc });
+            }
+            state.word = state.read(pc)?;
+            let invalid = Error::InvalidInstruction { pc, instruction: state.word };
+            if !(0..100000).contains(&state.word) {
+                return Err(invalid);
+            }
+            let opcode = state.word % 100;
+            let (params, written) = match opcode {
+                1 | 2 | 7 | 8 => (3, Some(3)),
+                3 => (1, Some(1)),
+                4 | 9 => (1, None),
+                5 | 6 => (2, None),
+                99 => (0, None),
+                _ => return Err(invalid),
+            };
+            for param in 1..=params {
+                match state.mode(param) {
+                    1 if written == Some(param) => return Err(Error::ImmediateWrite { pc, param: param as usize }),
+                    0..=2 => {}
+                    mode => return Err(Error::InvalidMode { pc, param: param as usize, mode: mode as i32 }),
+                }
+            }
+            steps += 1;
+            let mut next = pc + params as i64 + 1;
+            match opcode {
+                1 | 2 | 7 | 8 => {
+                    let (a, b) = (state.value(1)?, state.value(2)?);
+                    let val = match opcode {
+                        1 => a.wrapping_add(b),
+                        2 => a.wrapping_mul(b),
+                        7 => (a < b) as i64,
+                        _ => (a == b) as i64,
+                    };
+                    state.write(state.addr(3)?, val)?;
+                }
+                3 => {
+                    let addr = state.addr(1)?;
+                    let val = *input.next().ok_or(Error::InputExhausted { pc })?;
+                    state.write(addr, val)?;
+                }
+                4 => output.push(state.value(1)?),
+                5 | 6 => {
+                    let (condition, target) = (state.value(1)?, state.value(2)?);
+                    if (condition != 0) == (opcode == 5) {
+                        next = target;
+                    }
+                }
+                9 => state.base = state.base.saturating_add(state.value(1)?),
+                _ => return Ok(Run { output, memory: state.memory }),
+            }
+            state.pc = next;
+        }
+    }
+}
+
+// only core and alloc, so these also run without the std feature
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn it_passes_with_every_engine() {
+        assert_eq!(check(&Interpreter), vec![]);
+        assert_eq!(check(&Compiled), vec![]);
+        assert_eq!(check(&Reference::default()), vec![]);
+    }
+
+    // reports the memory the program started with
+    struct Broken;
+
+    impl Engine for Broken {
+        fn run(&self, program: &[i64], input: &[i64]) -> Result<Run, Error> {
+            Ok(Run { memory: program.to_vec(), ..Interpreter.run(program, input)? })
+        }
+    }
+
+    #[test]
+    fn it_reports_what_an_engine_got_wrong() {
+        let failures = check(&Broken);
+        assert_eq!(failures.len(), 7);
+        assert_eq!(failures[1].to_string(), "day 2: 1 + 1: memory [1, 0, 0, 0, 99], expected [2, 0, 0, 0, 99]");
+    }
+
+    const BUDGET: u64 = 500;
+
+    // valid instructions followed by some data; operands are addresses inside the program or
+    // small immediates, so jumps mostly land somewhere and loops run into the budget
+    fn programs() -> impl Strategy<Value = Vec<i64>> {
+        let instruction = (
+            prop::sample::select(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 99]),
+            prop::array::uniform3(0..3i64),
+            prop::array::uniform3(any::<u16>()),
+        );
+        let params = |opcode: i64| match opcode {
+            1 | 2 | 7 | 8 => 3,
+            3 | 4 | 9 => 1,
+            5 | 6 => 2,
+            _ => 0,
+        };
+        (prop::collection::vec(instruction, 1..16), prop::collection::vec(-20..100i64, 0..8)).prop_map(
+            move |(instructions, data)| {
+                let len = (instructions.iter().map(|(opcode, ..)| params(*opcode) + 1).sum::<usize>() + data.len()) as i64;
+                let mut program = vec![];
+                for (opcode, mut modes, raw) in instructions {
+                    let written = match opcode {
+                        1 | 2 | 7 | 8 => 2,
+                        3 => 0,
+                        _ => 3,
+                    };
+                    if modes[written.min(2)] == 1 && written < 3 {
+                        modes[written] = 0;
+                    }
+                    let params = params(opcode);
+                    program.push(opcode + (0..params).map(|i| modes[i] * 10i64.pow(i as u32 + 2)).sum::<i64>());
+                    for i in 0..params {
+                        program.push(match modes[i] {
+                            1 => raw[i] as i64 % (len + 8) - 4,
+                            _ => raw[i] as i64 % len,
+                        });
+                    }
+                }
+                program.extend(data);
+                program
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn it_agrees_with_the_reference(program in programs(), input in prop::collection::vec(-100..100i64, 0..4)) {
+            let expected = Reference { budget: Some(BUDGET), memory_multiplier: 64 }.run(&program, &input);
+            let builder = || MachineBuilder::new().instruction_budget(BUDGET).memory_multiplier(64);
+            prop_assert_eq!(&run_machine(builder(), &program, &input, false), &expected);
+            prop_assert_eq!(&run_machine(builder(), &program, &input, true), &expected);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Debug symbols for assembled programs.
+//!
+//! [`crate::asm::assemble`] and [`crate::link::link`] produce a [`DebugInfo`] alongside the
+//! program: where each label is and which source line every address came from. With it errors
+//! (see [`Machine::describe_error`]), [`crate::TraceEvent`]s and the tracer (see
+//! [`crate::MachineBuilder::debug_info`]) can point at `loop+2` on line 5 instead of a bare pc.
+//!
+//! [`DebugInfo::to_map`] saves it next to the program as a map file, one entry per line:
+//!
+//! ```text
+//! len 13
+//! label loop 2
+//! line 0 2 IN [n]
+//! line 2 3 loop:   OUT [n]
+//! ```
+//!
+//! `len` is the program length, `label` a name and its address and `line` the address, line
+//! number and text of a source line. [`DebugInfo::from_map`] reads it back. With the `serde`
+//! feature it can be saved with the `json` module as well.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use crate::{Error, Machine, TraceEvent, Word};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugInfo {
+    /// Label addresses by name.
+    pub labels: BTreeMap<String, i64>,
+    /// The source line of every instruction and `DATA` directive, by the address it starts at.
+    pub lines: BTreeMap<i64, Line>,
+    /// The length of the program; nothing past it has a source line.
+    pub len: i64,
+}
+
+/// Counted from 1, the text is without comments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Line {
+    pub number: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// A line of the map file that isn't a `len`, `label` or `line` entry, counted from 1.
+    InvalidEntry { line: usize },
+    MissingLength,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::InvalidEntry { line } => write!(f, "line {}: invalid map entry", line),
+            MapError::MissingLength => write!(f, "the map has no len entry"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MapError {}
+
+impl DebugInfo {
+    /// The map file for the program, see the module docs.
+    pub fn to_map(&self) -> String {
+        let mut map = format!("len {}\n", self.len);
+        for (name, addr) in &self.labels {
+            map += &format!("label {} {}\n", name, addr);
+        }
+        for (addr, line) in &self.lines {
+            map += &format!("line {} {} {}\n", addr, line.number, line.text);
+        }
+        map
+    }
+
+    pub fn from_map(map: &str) -> Result<DebugInfo, MapError> {
+        let mut debug = DebugInfo::default();
+        let mut len = None;
+        for (i, entry) in map.lines().enumerate().filter(|(_, entry)| !entry.trim().is_empty()) {
+            let invalid = MapError::InvalidEntry { line: i + 1 };
+            let mut fields = entry.splitn(4, ' ');
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some("len"), Some(n), None, None) => len = Some(n.parse().map_err(|_| invalid)?),
+                (Some("label"), Some(name), Some(addr), None) => {
+                    let addr = addr.parse().map_err(|_| invalid)?;
+                    debug.labels.insert(name.to_string(), addr);
+                }
+                (Some("line"), Some(addr), Some(number), text) => {
+                    let addr = addr.parse().map_err(|_| invalid.clone())?;
+                    let number = number.parse().map_err(|_| invalid)?;
+                    debug.lines.insert(addr, Line { number, text: text.unwrap_or("").to_string() });
+                }
+                _ => return Err(invalid),
+            }
+        }
+        debug.len = len.ok_or(MapError::MissingLength)?;
+        Ok(debug)
+    }
+
+    /// The closest label at or before `addr`, and how far past it `addr` is.
+    pub fn symbolize(&self, addr: i64) -> Option<(&str, i64)> {
+        if !(0..self.len).contains(&addr) {
+            return None;
+        }
+        self.labels
+            .iter()
+            .filter(|(_, &label)| label <= addr)
+            .max_by_key(|(_, &label)| label)
+            .map(|(name, label)| (name.as_str(), addr - label))
+    }
+
+    /// The line that the word at `addr` was assembled from.
+    pub fn line(&self, addr: i64) -> Option<&Line> {
+        if !(0..self.len).contains(&addr) {
+            return None;
+        }
+        self.lines.range(..=addr).next_back().map(|(_, line)| line)
+    }
+
+    /// `addr` with its label and source line, like `4 (loop+2), line 5: ADD [n] #-1 [n]`.
+    pub fn describe(&self, addr: i64) -> String {
+        let mut description = format!("{}", addr);
+        match self.symbolize(addr) {
+            Some((label, 0)) => description += &format!(" ({})", label),
+            Some((label, offset)) => description += &format!(" ({}+{})", label, offset),
+            None => {}
+        }
+        if let Some(line) = self.line(addr) {
+            description += &format!(", line {}: {}", line.number, line.text);
+        }
+        description
+    }
+
+    /// The error's message followed by where it happened, see also [`Machine::describe_error`].
+    pub fn describe_error(&self, error: &Error) -> String {
+        let mut description = format!("{}\n  at {}", error, self.describe(error.pc()));
+        if let Error::InvalidAddress { addr, .. } = error {
+            match self.symbolize(*addr) {
+                Some((label, 0)) => description += &format!("\n  address {} is {}", addr, label),
+                Some((label, offset)) => description += &format!("\n  address {} is {}+{}", addr, label, offset),
+                None => {}
+            }
+        }
+        description
+    }
+
+    pub fn describe_event<W: Word>(&self, event: &TraceEvent<W>) -> String {
+        format!("step {}: {}", event.step, self.describe(event.pc))
+    }
+}
+
+impl<W: Word> Machine<W> {
+    /// The error's message, followed by its label and source line if the machine has debug info.
+    pub fn describe_error(&self, error: &Error) -> String {
+        match &self.debug_info {
+            Some(debug) => debug.describe_error(error),
+            None => error.to_string(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{DebugInfo, MapError};
+    use crate::asm::assemble;
+    use crate::{Error, MachineBuilder};
+
+    #[test]
+    fn it_describes_addresses_errors_and_events() {
+        let assembly = assemble(
+            "
+        IN [n]
+loop:   OUT [n]     ; counts down
+        ADD [n] #-1 [n]
+        JT [n] #loop
+        DATA 42
+n:      DATA 0
+",
+        )
+        .unwrap();
+        let debug = &assembly.debug;
+        assert_eq!(debug.describe(2), "2 (loop), line 3: loop:   OUT [n]");
+        assert_eq!(debug.describe(5), "5 (loop+3), line 4: ADD [n] #-1 [n]");
+        assert_eq!(debug.describe(12), "12 (n), line 7: n:      DATA 0");
+        assert_eq!(debug.describe(13), "13");
+
+        let mut m = MachineBuilder::new()
+            .trace(false)
+            .record_trace(true)
+            .debug_info(debug.clone())
+            .build_detached(assembly.program.clone());
+        m.set_input_string("1\n".to_string());
+        let err = m.execute().unwrap_err();
+        assert_eq!(m.describe_error(&err), "invalid instruction 42 at pc 11\n  at 11 (loop+9), line 6: DATA 42");
+        assert_eq!(debug.describe_event(&m.trace_events()[2]), "step 2: 4 (loop+2), line 4: ADD [n] #-1 [n]");
+        assert_eq!(
+            debug.describe_error(&Error::InvalidAddress { pc: 0, addr: 12 }),
+            "invalid address 12 at pc 0\n  at 0, line 2: IN [n]\n  address 12 is n"
+        );
+    }
+
+    #[test]
+    fn it_saves_and_reads_map_files() {
+        let debug = assemble("loop: IN [n]\n  JT [n] #loop\nn: DATA 0\n").unwrap().debug;
+        let map = debug.to_map();
+        assert_eq!(
+            map,
+            "len 6\nlabel loop 0\nlabel n 5\nline 0 1 loop: IN [n]\nline 2 2 JT [n] #loop\nline 5 3 n: DATA 0\n"
+        );
+        assert_eq!(DebugInfo::from_map(&map), Ok(debug));
+
+        assert_eq!(DebugInfo::from_map("len 6\nlabel loop\n"), Err(MapError::InvalidEntry { line: 2 }));
+        assert_eq!(DebugInfo::from_map("line 0 x IN [n]"), Err(MapError::InvalidEntry { line: 1 }));
+        assert_eq!(DebugInfo::from_map("label loop 0\n"), Err(MapError::MissingLength));
+
+        // without debug info errors are just their message
+        let (_, m) = MachineBuilder::new().trace(false).build(vec![99]);
+        assert_eq!(m.describe_error(&Error::InputExhausted { pc: 0 }), Error::InputExhausted { pc: 0 }.to_string());
+    }
+}
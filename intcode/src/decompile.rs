@@ -0,0 +1,624 @@
+//! Decompilation of intcode programs to C-like pseudocode.
+//!
+//! [`decompile`] builds on [`crate::cfg`]: backward jumps become `loop`, `while` and `do`/`while`
+//! loops, forward branches become `if`/`else`, and whatever doesn't fit is left as `goto`.
+//! Memory cells are named after their address (`v42`).
+//!
+//! Calls are recognised by the stack frame convention [`crate::lang`] compiles to. With the
+//! caller's frame `S` cells long, a call stores the return address in `[rb+S]` and the arguments
+//! in `[rb+S+1]` onwards, moves the relative base up by `S` and jumps to the function, which
+//! returns by jumping to `[rb+0]` with its result in `[rb+1]`. The instruction at the return
+//! address moves the relative base back down. Inside a function the relative cells are named
+//! `ret` for the return address, `p1`, `p2`, ... for parameters and `l3`, `l4`, ... for locals.
+//! Code moving the relative base any other way keeps the raw `rb[offset]` form.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::cfg::{Cfg, Exit};
+use crate::decode;
+
+/// Pseudocode for `program`, one function after the other starting with `main` at address 0.
+pub fn decompile(program: &[i64]) -> String {
+    let decompiler = Decompiler::new(program);
+    let mut src = String::new();
+    for (i, function) in decompiler.functions.values().enumerate() {
+        if i > 0 {
+            writeln!(src).unwrap();
+        }
+        src.push_str(&Emitter::new(&decompiler, function).emit());
+    }
+    src
+}
+
+// The parts of a call sequence at the end of a block.
+struct Call {
+    target: usize,
+    // size of the caller's frame, the relative base moves up by this much
+    frame: i64,
+    ret: usize,
+    // the instructions making up the call, not shown as statements of their own
+    parts: BTreeSet<usize>,
+    // argument number -> the instruction storing it
+    args: BTreeMap<i64, usize>,
+}
+
+struct Function {
+    entry: usize,
+    blocks: BTreeSet<usize>,
+    params: i64,
+    // false if the relative base moves in ways other than calls
+    framed: bool,
+}
+
+struct Decompiler<'a> {
+    program: &'a [i64],
+    cfg: Cfg,
+    // by the start of the block they end
+    calls: BTreeMap<usize, Call>,
+    functions: BTreeMap<usize, Function>,
+}
+
+// A decoded instruction: opcode, modes and parameters.
+#[derive(Clone)]
+struct Instr {
+    opcode: i32,
+    modes: [i32; 3],
+    args: Vec<i64>,
+}
+
+fn instr_at(program: &[i64], addr: usize) -> Option<Instr> {
+    let instr = decode(*program.get(addr)?)?;
+    let len = crate::instruction_length(instr.opcode)?;
+    let args = program.get(addr + 1..addr + len)?.to_vec();
+    Some(Instr { opcode: instr.opcode, modes: [instr.mode_op1, instr.mode_op2, instr.mode_op3], args })
+}
+
+impl Instr {
+    // (mode, parameter) of the cell written, if any
+    fn written(&self) -> Option<(i32, i64)> {
+        match self.opcode {
+            1 | 2 | 7 | 8 => Some((self.modes[2], self.args[2])),
+            3 => Some((self.modes[0], self.args[0])),
+            _ => None,
+        }
+    }
+
+    // an immediate jump that is always taken
+    fn jump_target(&self) -> Option<usize> {
+        let always = match self.opcode {
+            5 => self.args[0] != 0,
+            6 => self.args[0] == 0,
+            _ => false,
+        };
+        if always && self.modes[0] == 1 && self.modes[1] == 1 && self.args[1] >= 0 {
+            Some(self.args[1] as usize)
+        } else {
+            None
+        }
+    }
+
+    // jumps to the address in [rb+0]
+    fn is_return(&self) -> bool {
+        let always = match self.opcode {
+            5 => self.args[0] != 0,
+            6 => self.args[0] == 0,
+            _ => false,
+        };
+        always && self.modes[0] == 1 && self.modes[1] == 2 && self.args[1] == 0
+    }
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(program: &'a [i64]) -> Self {
+        // every call adds its target and return address as new places to start from
+        let mut entries = vec![0];
+        let (cfg, calls) = loop {
+            let cfg = Cfg::build_from(program, &entries);
+            let calls = cfg
+                .blocks
+                .values()
+                .filter_map(|block| find_call(program, &block.instructions).map(|call| (block.start, call)))
+                .collect::<BTreeMap<_, _>>();
+            let before = entries.len();
+            for call in calls.values() {
+                for addr in [call.target, call.ret] {
+                    if !entries.contains(&addr) {
+                        entries.push(addr);
+                    }
+                }
+            }
+            if entries.len() == before {
+                break (cfg, calls);
+            }
+        };
+        let mut decompiler = Decompiler { program, cfg, calls, functions: BTreeMap::new() };
+        let mut entries = decompiler.calls.values().map(|call| call.target).collect::<BTreeSet<_>>();
+        entries.insert(0);
+        for entry in entries {
+            let function = decompiler.function(entry);
+            decompiler.functions.insert(entry, function);
+        }
+        decompiler
+    }
+
+    // blocks control can go to next, stepping over calls
+    fn successors(&self, start: usize) -> Vec<usize> {
+        if let Some(call) = self.calls.get(&start) {
+            return vec![call.ret];
+        }
+        self.cfg.blocks.get(&start).map(|block| block.successors.clone()).unwrap_or_default()
+    }
+
+    fn function(&self, entry: usize) -> Function {
+        let mut blocks = BTreeSet::new();
+        let mut work = vec![entry];
+        while let Some(start) = work.pop() {
+            if self.cfg.blocks.contains_key(&start) && blocks.insert(start) {
+                work.extend(self.successors(start));
+            }
+        }
+        let returns = self.calls.values().map(|call| call.ret).collect::<BTreeSet<_>>();
+        let parts = self.calls.values().flat_map(|call| call.parts.iter().cloned()).collect::<BTreeSet<_>>();
+        let framed = blocks.iter().flat_map(|start| self.cfg.blocks[start].instructions.iter()).all(|&pc| {
+            let is_arb = instr_at(self.program, pc).is_some_and(|instr| instr.opcode == 9);
+            // the stack set up at the very start of the program is fine too
+            !is_arb || parts.contains(&pc) || returns.contains(&pc) || (pc == 0 && self.program[0] == 109)
+        });
+        let params = self
+            .calls
+            .values()
+            .filter(|call| call.target == entry)
+            .filter_map(|call| call.args.keys().next_back().cloned())
+            .max()
+            .unwrap_or(0);
+        Function { entry, blocks, params, framed }
+    }
+}
+
+// Does the block end in a call, see the module docs.
+fn find_call(program: &[i64], instructions: &[usize]) -> Option<Call> {
+    let n = instructions.len();
+    if n < 3 {
+        return None;
+    }
+    let jump = instructions[n - 1];
+    let target = instr_at(program, jump)?.jump_target()?;
+    let arb = instructions[n - 2];
+    if program[arb] != 109 || program[arb + 1] <= 0 {
+        return None;
+    }
+    let frame = program[arb + 1];
+    let ret = jump + 3;
+    if program.get(ret..ret + 2) != Some(&[109, -frame]) {
+        return None;
+    }
+    let mut call = Call { target, frame, ret, parts: [arb, jump].iter().cloned().collect(), args: BTreeMap::new() };
+    for &pc in &instructions[..n - 2] {
+        let instr = match instr_at(program, pc) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        match instr.written() {
+            // the return address
+            Some((2, offset))
+                if offset == frame
+                    && instr.opcode == 1
+                    && instr.modes[..2] == [1, 1]
+                    && instr.args[0].checked_add(instr.args[1]) == Some(ret as i64) =>
+            {
+                call.parts.insert(pc);
+            }
+            Some((2, offset)) if offset > frame => {
+                call.args.insert(offset - frame, pc);
+            }
+            _ => {}
+        }
+    }
+    if call.parts.len() < 3 {
+        return None; // no return address
+    }
+    call.parts.extend(call.args.values().cloned());
+    Some(call)
+}
+
+enum Line {
+    Label(usize),
+    Text(usize, String),
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    head: usize,
+    exit: usize,
+    // the block whose branch back to `head` is the condition of a do/while loop
+    latch: Option<usize>,
+}
+
+struct Emitter<'a> {
+    d: &'a Decompiler<'a>,
+    f: &'a Function,
+    lines: Vec<Line>,
+    gotos: BTreeSet<usize>,
+    // blocks whose final jump goes to the end of an if/else and is left out
+    joins: BTreeSet<usize>,
+}
+
+impl<'a> Emitter<'a> {
+    fn new(d: &'a Decompiler<'a>, f: &'a Function) -> Self {
+        Emitter { d, f, lines: vec![], gotos: BTreeSet::new(), joins: BTreeSet::new() }
+    }
+
+    fn emit(mut self) -> String {
+        let first = *self.f.blocks.iter().next().unwrap_or(&self.f.entry);
+        if first != self.f.entry {
+            self.gotos.insert(self.f.entry);
+            self.lines.push(Line::Text(1, format!("goto L{};", self.f.entry)));
+        }
+        self.region(first, usize::MAX, None, 1);
+
+        let mut src = match self.f.entry {
+            0 => "fn main() {\n".to_string(),
+            entry => {
+                let params = (1..=self.f.params).map(|k| format!("p{}", k)).collect::<Vec<_>>();
+                format!("fn f{}({}) {{\n", entry, params.join(", "))
+            }
+        };
+        for line in &self.lines {
+            match line {
+                Line::Label(addr) if self.gotos.contains(addr) => writeln!(src, "L{}:", addr).unwrap(),
+                Line::Label(_) => {}
+                Line::Text(depth, text) => writeln!(src, "{}{}", "    ".repeat(*depth), text).unwrap(),
+            }
+        }
+        src.push_str("}\n");
+        src
+    }
+
+    fn push(&mut self, depth: usize, text: String) {
+        self.lines.push(Line::Text(depth, text));
+    }
+
+    fn next_block(&self, addr: usize) -> usize {
+        self.f.blocks.range(addr + 1..).next().cloned().unwrap_or(usize::MAX)
+    }
+
+    fn instr(&self, pc: usize) -> Option<Instr> {
+        instr_at(self.d.program, pc)
+    }
+
+    fn relative(&self, offset: i64) -> String {
+        match offset {
+            _ if !self.f.framed || offset < 0 => format!("rb[{}]", offset),
+            0 if self.f.entry != 0 => "ret".to_string(),
+            _ if self.f.entry != 0 && offset <= self.f.params => format!("p{}", offset),
+            _ => format!("l{}", offset),
+        }
+    }
+
+    fn operand(&self, instr: &Instr, i: usize) -> String {
+        match instr.modes[i] {
+            1 => instr.args[i].to_string(),
+            2 => self.relative(instr.args[i]),
+            _ => format!("v{}", instr.args[i]),
+        }
+    }
+
+    // the value an instruction computes and where it goes
+    fn assignment(&self, instr: &Instr) -> Option<(String, String)> {
+        let (a, b) = (|| self.operand(instr, 0), || self.operand(instr, 1));
+        let value = match instr.opcode {
+            1 if instr.modes[1] == 1 && instr.args[1] == 0 => a(),
+            1 if instr.modes[0] == 1 && instr.args[0] == 0 => b(),
+            1 if instr.modes[1] == 1 && instr.args[1] < 0 => format!("{} - {}", a(), instr.args[1].unsigned_abs()),
+            1 => format!("{} + {}", a(), b()),
+            2 if instr.modes[1] == 1 && instr.args[1] == -1 => format!("-{}", a()),
+            2 => format!("{} * {}", a(), b()),
+            3 => "input()".to_string(),
+            7 => format!("{} < {}", a(), b()),
+            8 => format!("{} == {}", a(), b()),
+            _ => return None,
+        };
+        let target = if instr.opcode == 3 { 0 } else { 2 };
+        Some((self.operand(instr, target), value))
+    }
+
+    fn statements(&mut self, start: usize, depth: usize) {
+        let block = &self.d.cfg.blocks[&start];
+        let call = self.d.calls.get(&start);
+        let returns = self.d.calls.values().any(|call| call.ret == start);
+        for (i, &pc) in block.instructions.iter().enumerate() {
+            let instr = match self.instr(pc) {
+                Some(instr) => instr,
+                None => continue,
+            };
+            if call.is_some_and(|call| call.parts.contains(&pc)) || (returns && i == 0) {
+                continue;
+            }
+            if let Some((target, value)) = self.assignment(&instr) {
+                self.push(depth, format!("{} = {};", target, value));
+                continue;
+            }
+            match instr.opcode {
+                4 => self.push(depth, format!("output({});", self.operand(&instr, 0))),
+                9 if pc == 0 && self.f.framed => self.push(depth, format!("// stack frames start at {}", instr.args[0])),
+                9 => self.push(depth, format!("rb += {};", self.operand(&instr, 0))),
+                _ => {} // jumps and halts end blocks
+            }
+        }
+        if let Some(call) = call {
+            let args = (1..=call.args.keys().next_back().cloned().unwrap_or(0))
+                .map(|k| match call.args.get(&k).and_then(|&pc| self.instr(pc)) {
+                    Some(instr) => self.assignment(&instr).unwrap().1,
+                    None => "?".to_string(),
+                })
+                .collect::<Vec<_>>();
+            let result = self.relative(call.frame + 1);
+            self.push(depth, format!("{} = f{}({});", result, call.target, args.join(", ")));
+        }
+    }
+
+    // `taken` is the condition under which the branch at the end of `start` is taken
+    fn condition(&self, start: usize, taken: bool) -> String {
+        let pc = *self.d.cfg.blocks[&start].instructions.last().unwrap();
+        let instr = self.instr(pc).unwrap();
+        let cond = self.operand(&instr, 0);
+        if (instr.opcode == 5) == taken {
+            cond
+        } else {
+            format!("!{}", cond)
+        }
+    }
+
+    // the statement for a jump to `target`, None if control just continues there
+    fn jump(&mut self, target: usize, until: usize, lp: Option<Loop>) -> Option<String> {
+        match lp {
+            Some(lp) if lp.head == target => Some("continue;".to_string()),
+            Some(lp) if lp.exit == target => Some("break;".to_string()),
+            _ if target == until => None,
+            _ => {
+                self.gotos.insert(target);
+                Some(format!("goto L{};", target))
+            }
+        }
+    }
+
+    // Emit the blocks in [from, until). True if control never runs off the end.
+    fn region(&mut self, from: usize, until: usize, lp: Option<Loop>, depth: usize) -> bool {
+        let mut cur = from;
+        let mut terminated = false;
+        while cur < until && self.f.blocks.contains(&cur) {
+            self.lines.push(Line::Label(cur));
+            if lp.is_none_or(|lp| lp.head != cur) {
+                let latch = self.f.blocks.range(cur..until).rev().find(|&&b| self.d.successors(b).contains(&cur));
+                if let Some(&latch) = latch {
+                    let exit = self.next_block(latch).min(until);
+                    self.looped(cur, latch, exit, depth);
+                    cur = exit;
+                    terminated = false;
+                    continue;
+                }
+            }
+            let (ends, next) = self.block(cur, until, lp, depth);
+            terminated = ends;
+            cur = next;
+        }
+        terminated
+    }
+
+    fn looped(&mut self, head: usize, latch: usize, exit: usize, depth: usize) {
+        let block = &self.d.cfg.blocks[&head];
+        let after_head = self.next_block(head);
+        let body_ends = if block.instructions.len() == 1
+            && block.exit == Exit::Branch
+            && !self.d.calls.contains_key(&head)
+            && block.successors == [exit, after_head]
+        {
+            let cond = self.condition(head, false);
+            self.push(depth, format!("while ({}) {{", cond));
+            let lp = Loop { head, exit, latch: None };
+            self.region(after_head, exit, Some(lp), depth + 1)
+        } else if latch != head && self.d.cfg.blocks[&latch].exit == Exit::Branch && self.d.cfg.blocks[&latch].successors == [head, exit] {
+            self.push(depth, "do {".to_string());
+            let lp = Loop { head, exit, latch: Some(latch) };
+            self.region(head, exit, Some(lp), depth + 1);
+            let cond = self.condition(latch, true);
+            self.push(depth, format!("}} while ({});", cond));
+            return;
+        } else {
+            self.push(depth, "loop {".to_string());
+            let lp = Loop { head, exit, latch: None };
+            self.region(head, exit, Some(lp), depth + 1)
+        };
+        // running off the end of the body leaves the loop, jumping back to the head doesn't
+        match self.lines.last() {
+            Some(Line::Text(d, text)) if *d == depth + 1 && text == "continue;" => {
+                self.lines.pop();
+            }
+            _ if !body_ends => self.push(depth + 1, "break;".to_string()),
+            _ => {}
+        }
+        self.push(depth, "}".to_string());
+    }
+
+    // Emit one block and whatever it consumes. Returns whether control runs off the end and
+    // the block to go on with.
+    fn block(&mut self, start: usize, until: usize, lp: Option<Loop>, depth: usize) -> (bool, usize) {
+        self.statements(start, depth);
+        let next = self.next_block(start);
+        if let Some(call) = self.d.calls.get(&start) {
+            return (false, call.ret);
+        }
+        let block = &self.d.cfg.blocks[&start];
+        let last = *block.instructions.last().unwrap();
+        let successors = block.successors.clone();
+        match block.exit {
+            Exit::Halt => {
+                self.push(depth, "halt;".to_string());
+                (true, next)
+            }
+            Exit::FallThrough if successors[0] == next => (false, next),
+            Exit::FallThrough | Exit::Jump if self.joins.contains(&start) => (false, next),
+            Exit::FallThrough | Exit::Jump => match self.jump(successors[0], until, lp) {
+                Some(stmt) => {
+                    self.push(depth, stmt);
+                    (true, next)
+                }
+                None => (false, next),
+            },
+            Exit::Branch if lp.is_some_and(|lp| lp.latch == Some(start)) => (true, next),
+            // jumping to the next instruction either way, the condition doesn't matter
+            Exit::Branch if successors[0] == successors[1] => (false, successors[1]),
+            Exit::Branch => {
+                let (taken, fall) = (successors[0], successors[1]);
+                let special = lp.is_some_and(|lp| taken == lp.head || taken == lp.exit);
+                if special || taken <= start || taken > until || fall != next {
+                    let cond = self.condition(start, true);
+                    if let Some(stmt) = self.jump(taken, until, lp) {
+                        self.push(depth, format!("if ({}) {}", cond, stmt));
+                    }
+                    return (false, fall);
+                }
+                // not taken runs [fall, taken), possibly ending in a jump over an else part
+                let cond = self.condition(start, false);
+                self.push(depth, format!("if ({}) {{", cond));
+                let then_last = *self.f.blocks.range(fall..taken).next_back().unwrap();
+                let then_block = &self.d.cfg.blocks[&then_last];
+                let else_end = match (then_block.exit, then_block.successors.first()) {
+                    (Exit::Jump, Some(&end)) if end > taken && end <= until && !self.d.calls.contains_key(&then_last) => {
+                        let special = lp.is_some_and(|lp| end == lp.head || end == lp.exit);
+                        Some(end).filter(|_| !special)
+                    }
+                    _ => None,
+                };
+                match else_end {
+                    Some(end) => {
+                        self.joins.insert(then_last);
+                        let then_ends = self.region(fall, taken, lp, depth + 1);
+                        self.push(depth, "} else {".to_string());
+                        let else_ends = self.region(taken, end, lp, depth + 1);
+                        self.push(depth, "}".to_string());
+                        (then_ends && else_ends, end)
+                    }
+                    None => {
+                        self.region(fall, taken, lp, depth + 1);
+                        self.push(depth, "}".to_string());
+                        (false, taken)
+                    }
+                }
+            }
+            Exit::IndirectJump => {
+                let instr = self.instr(last).unwrap();
+                if instr.is_return() && self.f.framed && self.f.entry != 0 {
+                    let result = self.relative(1);
+                    self.push(depth, format!("return {};", result));
+                    return (true, next);
+                }
+                let target = format!("goto *{};", self.operand(&instr, 1));
+                if successors.is_empty() {
+                    self.push(depth, target);
+                    (true, next)
+                } else {
+                    let cond = self.condition(start, true);
+                    self.push(depth, format!("if ({}) {}", cond, target));
+                    (false, next)
+                }
+            }
+            Exit::Invalid => {
+                self.push(depth, format!("// invalid instruction at {}", last));
+                (true, next)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Machine, Status};
+
+    #[test]
+    fn it_recovers_loops_and_branches() {
+        #[rustfmt::skip]
+        let program = [
+            3, 100,              // 0
+            1006, 100, 26,       // 2: loop head
+            1007, 100, 3, 101,   // 5
+            1006, 101, 17,       // 9: jump to the else part
+            104, 1,              // 12
+            1105, 1, 19,         // 14: jump over the else part
+            104, 2,              // 17
+            1001, 100, -1, 100,  // 19
+            1105, 1, 2,          // 23: back to the head
+            99,                  // 26
+        ];
+        assert_eq!(decompile(&program), "\
+fn main() {
+    v100 = input();
+    while (v100) {
+        v101 = v100 < 3;
+        if (v101) {
+            output(1);
+        } else {
+            output(2);
+        }
+        v100 = v100 - 1;
+    }
+    halt;
+}
+");
+        let (_, mut m) = Machine::new(program.to_vec());
+        m.set_input_string("4\n".to_string());
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![2, 2, 1, 1]);
+
+        // without the call convention the relative base stays visible
+        assert_eq!(decompile(&[9, 7, 204, -1, 99, 0, 0, 3]), "fn main() {\n    rb += v7;\n    output(rb[-1]);\n    halt;\n}\n");
+    }
+
+    #[test]
+    fn it_recovers_function_calls() {
+        #[rustfmt::skip]
+        let program = [
+            109, 200,            // 0: stack
+            3, 100,              // 2
+            21101, 17, 0, 2,     // 4: return address
+            21001, 100, 0, 3,    // 8: argument
+            109, 2,              // 12
+            1105, 1, 22,         // 14: call
+            109, -2,             // 17: return address
+            204, 3,              // 19: result
+            99,                  // 21
+            21202, 1, 2, 1,      // 22: double the argument
+            2105, 1, 0,          // 26: return
+        ];
+        assert_eq!(decompile(&program), "\
+fn main() {
+    // stack frames start at 200
+    v100 = input();
+    l3 = f22(v100);
+    output(l3);
+    halt;
+}
+
+fn f22(p1) {
+    p1 = p1 * 2;
+    return p1;
+}
+");
+        let (_, mut m) = Machine::new(program.to_vec());
+        m.set_input_string("21\n".to_string());
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![42]);
+    }
+
+    #[test]
+    fn it_treats_a_branch_to_the_next_instruction_as_fallthrough() {
+        assert_eq!(decompile(&[21006, 4, 3]), "fn main() {\n    // invalid instruction at 3\n}\n");
+        assert_eq!(decompile(&[3, 10, 1005, 10, 5, 99, 0, 0, 0, 0, 0]), "fn main() {\n    v10 = input();\n    halt;\n}\n");
+    }
+}
@@ -0,0 +1,209 @@
+//! Custom instructions.
+//!
+//! [`Machine::register_opcode`] adds an opcode without touching the interpreter, any opcode the
+//! built-in instruction set leaves free. The machine decodes the parameters according to the
+//! registered [`Param`] kinds and hands them to the handler. An error from the
+//! handler stops the machine the same way a failing built-in instruction does.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{builtin_params, Error, Machine, Word};
+
+/// How a parameter of a custom instruction is decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Param {
+    /// A value, read according to the parameter's mode like the operands of ADD.
+    Read,
+    /// An address to write to, passed through as-is like the last operand of ADD.
+    Write,
+}
+
+/// Why an opcode can't be registered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// Outside `0..=99`, no instruction word decodes to it.
+    OutOfRange { opcode: i32 },
+    /// The machine already implements it.
+    BuiltIn { opcode: i32 },
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::OutOfRange { opcode } => write!(f, "opcode {} is outside 0..=99", opcode),
+            RegisterError::BuiltIn { opcode } => write!(f, "opcode {} is a built-in instruction", opcode),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RegisterError {}
+
+/// What the machine does after a custom instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// Move on to the instruction following this one.
+    Continue,
+    /// Continue execution at this address.
+    Jump(i64),
+    /// Stop as if opcode 99 was hit.
+    Halt,
+}
+
+pub type Handler<W = i64> = Box<dyn FnMut(&mut Context<W>, &[W]) -> Result<Control, Error> + Send>;
+
+pub(crate) struct Extension<W> {
+    pub(crate) params: Vec<Param>,
+    pub(crate) handler: Handler<W>,
+}
+
+impl<W> fmt::Debug for Extension<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extension").field("params", &self.params).finish()
+    }
+}
+
+/// Access to the machine from inside a custom instruction.
+pub struct Context<'a, W = i64> {
+    pub(crate) machine: &'a mut Machine<W>,
+}
+
+impl<'a, W: Word> Context<'a, W> {
+    /// Address of the instruction being executed.
+    pub fn program_counter(&self) -> i64 {
+        self.machine.program_counter
+    }
+
+    /// [`Error::InvalidAddress`] if `addr` is outside of memory.
+    pub fn load(&self, addr: i64) -> Result<W, Error> {
+        self.machine.load(addr)
+    }
+
+    /// [`Error::InvalidAddress`] if `addr` is outside of memory.
+    pub fn store(&mut self, addr: i64, val: W) -> Result<(), Error> {
+        self.machine.store(addr, val)
+    }
+
+    /// Take the next value from the machine's input. A custom instruction can't pause halfway,
+    /// so running out of input is always [`Error::InputExhausted`].
+    pub fn input(&mut self) -> Result<W, Error> {
+        let pc = self.machine.program_counter;
+        self.machine.read_input()?.ok_or(Error::InputExhausted { pc })
+    }
+
+    /// Send a value to the machine's output, the same way opcode 4 does.
+    pub fn output(&mut self, val: W) -> Result<(), Error> {
+        self.machine.emit_output(val)
+    }
+}
+
+impl<W: Word> Machine<W> {
+    /// Add a custom instruction. `params` lists how each parameter is decoded; the handler
+    /// receives the decoded parameters in the same order. Registering a custom opcode again
+    /// replaces its handler.
+    pub fn register_opcode<F>(&mut self, opcode: i32, params: &[Param], handler: F) -> Result<(), RegisterError>
+    where
+        F: FnMut(&mut Context<W>, &[W]) -> Result<Control, Error> + Send + 'static,
+    {
+        if !(0..=99).contains(&opcode) {
+            return Err(RegisterError::OutOfRange { opcode });
+        }
+        if builtin_params(opcode).is_some() {
+            return Err(RegisterError::BuiltIn { opcode });
+        }
+        self.extensions.insert(opcode, Extension { params: params.to_vec(), handler: Box::new(handler) });
+        self.compiled.clear();
+        Ok(())
+    }
+
+    pub(crate) fn execute_extension(&mut self, opcode: i32, modes: [i32; 3]) -> Result<Control, Error> {
+        let params = &self.extensions[&opcode].params;
+        let mut operands = Vec::with_capacity(params.len());
+        for (i, param) in params.iter().enumerate() {
+            let addr = self.program_counter + 1 + i as i64;
+            let mode = modes.get(i).cloned().unwrap_or(0);
+            operands.push(match param {
+                Param::Read => self.load_with_mode(addr, mode)?,
+                Param::Write if mode == 2 => W::from_i64(self.write_address(addr, mode)?),
+                Param::Write => self.load(addr)?,
+            });
+        }
+        let mut ext = self.extensions.remove(&opcode).unwrap();
+        let control = (ext.handler)(&mut Context { machine: self }, &operands);
+        self.extensions.insert(opcode, ext);
+        control
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn it_runs_custom_instructions() {
+        // 10: AND, 11: debug print
+        let (_, mut m) = Machine::new(vec![10, 11, 12, 13, 4, 13, 1111, 42, 99, 0, 0, 12, 10, 0]);
+        m.register_opcode(10, &[Param::Read, Param::Read, Param::Write], |ctx, ops| {
+            ctx.store(ops[2], ops[0] & ops[1])?;
+            Ok(Control::Continue)
+        })
+        .unwrap();
+        let printed = Arc::new(Mutex::new(vec![]));
+        let p = printed.clone();
+        m.register_opcode(11, &[Param::Read], move |ctx, ops| {
+            p.lock().unwrap().push((ctx.program_counter(), ops[0]));
+            Ok(Control::Continue)
+        })
+        .unwrap();
+        m.execute().unwrap();
+
+        assert_eq!(m.get_output(), &vec![8]);
+        assert_eq!(*printed.lock().unwrap(), vec![(6, 42)]);
+    }
+
+    #[test]
+    fn it_lets_custom_instructions_jump_and_halt() {
+        // 20: unconditional jump, 21: halt
+        let (_, mut m) = Machine::new(vec![120, 5, 104, 1, 99, 104, 2, 21]);
+        m.register_opcode(20, &[Param::Read], |_, ops| Ok(Control::Jump(ops[0]))).unwrap();
+        m.register_opcode(21, &[], |_, _| Ok(Control::Halt)).unwrap();
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![2]);
+    }
+
+    #[test]
+    fn it_stops_on_errors_from_custom_instructions() {
+        // 10: read input into the parameter's address
+        let (_, mut m) = Machine::new(vec![10, 5, 10, 6, 99, 0, 0]);
+        m.register_opcode(10, &[Param::Write], |ctx, ops| {
+            let val = ctx.input()?;
+            ctx.store(ops[0], val)?;
+            Ok(Control::Continue)
+        })
+        .unwrap();
+        m.set_input_string("3\n".to_string());
+        assert_eq!(m.execute(), Err(Error::InputExhausted { pc: 2 }));
+        assert_eq!(m.peek(5), Some(3));
+
+        let (_, mut m) = Machine::new(vec![10, -1, 99]);
+        m.register_opcode(10, &[Param::Write], |ctx, ops| {
+            ctx.store(ops[0], 1)?;
+            Ok(Control::Continue)
+        })
+        .unwrap();
+        assert_eq!(m.execute(), Err(Error::InvalidAddress { pc: 0, addr: -1 }));
+    }
+
+    #[test]
+    fn it_rejects_opcodes_it_cannot_add() {
+        let (_, mut m) = Machine::new(vec![99]);
+        assert_eq!(m.register_opcode(100, &[], |_, _| Ok(Control::Halt)), Err(RegisterError::OutOfRange { opcode: 100 }));
+        assert_eq!(m.register_opcode(-1, &[], |_, _| Ok(Control::Halt)), Err(RegisterError::OutOfRange { opcode: -1 }));
+        assert_eq!(m.register_opcode(99, &[], |_, _| Ok(Control::Continue)), Err(RegisterError::BuiltIn { opcode: 99 }));
+        assert_eq!(m.register_opcode(9, &[Param::Read], |_, _| Ok(Control::Continue)), Err(RegisterError::BuiltIn { opcode: 9 }));
+        assert_eq!(m.execute(), Ok(crate::Status::Halted));
+    }
+}
@@ -0,0 +1,195 @@
+//! How often each memory cell was read, written and executed, as a picture.
+//!
+//! Counts come from replaying a recorded trace (see [`crate::MachineBuilder::record_trace`])
+//! over the program the machine started with, so the interpreter itself doesn't pay for them.
+//! [`Heatmap::to_ppm`] draws one square per cell, row by row: red for writes, green for reads
+//! and blue for executes, brighter the more often it happened. Code shows up blue, tables green,
+//! a stack yellow (read and written) and untouched memory black.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::extension::Param;
+use crate::{builtin_params, MachineBuilder, TraceEvent, Word};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: u64,
+    pub writes: u64,
+    /// Times the cell was part of an executed instruction, opcode or parameter.
+    pub executes: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Heatmap {
+    /// By address, up to the highest one accessed.
+    pub counts: Vec<AccessCounts>,
+    /// Accesses the trace has outside of memory, which aren't counted.
+    pub skipped: u64,
+    // addresses at and past this are outside of memory
+    limit: usize,
+}
+
+impl Heatmap {
+    /// Replay `events`, recorded from a machine that started out with `program` in memory.
+    /// Reads are the cells position and relative mode parameters point at, immediate parameters
+    /// only count as executed. Custom instructions aren't known here, so only their opcode is
+    /// counted. Memory is taken to be as large as a default [`MachineBuilder`] makes it.
+    pub fn from_trace<W: Word>(program: &[W], events: &[TraceEvent<W>]) -> Heatmap {
+        let memory_size = MachineBuilder::default().memory_size(program.len());
+        Heatmap::from_trace_with_memory(program, events, memory_size)
+    }
+
+    /// Like [`Heatmap::from_trace`] for a machine with `memory_size` cells.
+    pub fn from_trace_with_memory<W: Word>(program: &[W], events: &[TraceEvent<W>], memory_size: usize) -> Heatmap {
+        let mut heatmap = Heatmap { limit: memory_size, ..Heatmap::default() };
+        // memory as the trace changed it
+        let mut written = BTreeMap::new();
+        let load = |written: &BTreeMap<i64, i64>, addr: i64| {
+            written.get(&addr).copied().unwrap_or_else(|| {
+                usize::try_from(addr).ok().and_then(|addr| program.get(addr)).map_or(0, W::to_i64_saturating)
+            })
+        };
+        let mut relative_base = 0i64;
+        for event in events {
+            let word = event.instruction.to_i64_saturating();
+            let opcode = (word % 100) as i32;
+            heatmap.record(event.pc, |counts| counts.executes += 1);
+            let mut divisor = 100;
+            for (i, param) in builtin_params(opcode).unwrap_or(&[]).iter().enumerate() {
+                let addr = event.pc.saturating_add(i as i64 + 1);
+                let mode = word / divisor % 10;
+                divisor = divisor.saturating_mul(10);
+                heatmap.record(addr, |counts| counts.executes += 1);
+                if *param == Param::Write {
+                    continue;
+                }
+                let value = match mode {
+                    1 => load(&written, addr),
+                    _ => {
+                        let offset = if mode == 2 { relative_base } else { 0 };
+                        let target = offset.saturating_add(load(&written, addr));
+                        heatmap.record(target, |counts| counts.reads += 1);
+                        load(&written, target)
+                    }
+                };
+                if opcode == 9 {
+                    relative_base = relative_base.saturating_add(value);
+                }
+            }
+            for (addr, val) in &event.writes {
+                heatmap.record(*addr, |counts| counts.writes += 1);
+                written.insert(*addr, val.to_i64_saturating());
+            }
+        }
+        heatmap
+    }
+
+    fn record(&mut self, addr: i64, access: impl FnOnce(&mut AccessCounts)) {
+        match usize::try_from(addr) {
+            Ok(addr) if addr < self.limit => {
+                if addr >= self.counts.len() {
+                    self.counts.resize(addr + 1, AccessCounts::default());
+                }
+                access(&mut self.counts[addr]);
+            }
+            _ => self.skipped += 1,
+        }
+    }
+
+    /// A binary PPM (P6) image `width` cells wide, each drawn as a `scale` by `scale` square.
+    pub fn to_ppm(&self, width: usize, scale: usize) -> Vec<u8> {
+        let (width, scale) = (width.max(1), scale.max(1));
+        let rows = self.counts.len().div_ceil(width);
+        let max = self.counts.iter().fold(AccessCounts::default(), |max, c| AccessCounts {
+            reads: max.reads.max(c.reads),
+            writes: max.writes.max(c.writes),
+            executes: max.executes.max(c.executes),
+        });
+        let mut image = format!("P6\n{} {}\n255\n", width * scale, rows.max(1) * scale).into_bytes();
+        for row in 0..rows.max(1) {
+            let mut line = vec![];
+            for column in 0..width {
+                let cell = self.counts.get(row * width + column).copied().unwrap_or_default();
+                let pixel = [
+                    brightness(cell.writes, max.writes),
+                    brightness(cell.reads, max.reads),
+                    brightness(cell.executes, max.executes),
+                ];
+                for _ in 0..scale {
+                    line.extend_from_slice(&pixel);
+                }
+            }
+            for _ in 0..scale {
+                image.extend_from_slice(&line);
+            }
+        }
+        image
+    }
+}
+
+// 0 for nothing, then from 64 up to 255 for the most by orders of magnitude, so a few accesses
+// still show up next to a hot loop
+fn brightness(count: u64, max: u64) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let bits = |n: u64| (64 - n.leading_zeros()) as u64;
+    (64 + 191 * bits(count) / bits(max)) as u8
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_counts_accesses_and_draws_them() {
+        // outputs [14] counting down, through the relative base
+        #[rustfmt::skip]
+        let program = vec![
+            109, 15,            // ARB #15
+            204, -1,            // OUT [rb-1]
+            1001, 14, -1, 14,   // ADD [14] #-1 [14]
+            1005, 14, 2,        // JT [14] #2
+            99,                 // HALT
+            0, 0, 3,
+        ];
+        let (_, mut m) = MachineBuilder::new().trace(false).record_trace(true).build(program.clone());
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![3, 2, 1]);
+
+        let heatmap = Heatmap::from_trace(&program, m.trace_events());
+        assert_eq!(heatmap.counts.len(), 15);
+        assert_eq!(heatmap.counts[1], AccessCounts { reads: 0, writes: 0, executes: 1 });
+        assert_eq!(heatmap.counts[2], AccessCounts { reads: 0, writes: 0, executes: 3 });
+        // read by OUT, ADD and JT on every iteration, written by ADD
+        assert_eq!(heatmap.counts[14], AccessCounts { reads: 9, writes: 3, executes: 0 });
+        assert_eq!(heatmap.counts[13], AccessCounts::default());
+        assert_eq!(heatmap.skipped, 0);
+
+        let image = heatmap.to_ppm(8, 2);
+        let header = b"P6\n16 4\n255\n";
+        assert_eq!(&image[..header.len()], header);
+        assert_eq!(image.len(), header.len() + 16 * 4 * 3);
+        // executed code is blue, the counter yellow and past the end black
+        let pixel = |x: usize, y: usize| &image[header.len() + (y * 16 + x) * 3..][..3];
+        assert_eq!(pixel(4, 0), [0, 0, 255]);
+        assert_eq!(pixel(5, 1), [0, 0, 255]);
+        assert_eq!(pixel(12, 2), [255, 255, 0]);
+        assert_eq!(pixel(15, 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn it_skips_addresses_outside_of_memory() {
+        let event = |pc, writes| TraceEvent { step: 0, pc, instruction: 1101, writes, input: None, output: None };
+        let events = [event(0, vec![(1 << 40, 2)]), event(-4, vec![]), event(0, vec![(7, 2)])];
+        let heatmap = Heatmap::from_trace_with_memory(&[1101i64, 1, 1, 1 << 40], &events, 8);
+        assert_eq!(heatmap.counts.len(), 8);
+        assert_eq!(heatmap.counts[7].writes, 1);
+        // the first write, and the opcode and three parameters of the event at -4
+        assert_eq!(heatmap.skipped, 5);
+    }
+}
@@ -0,0 +1,99 @@
+//! JSON, the on-disk format for tooling.
+//!
+//! With the `serde` feature programs (`Vec<W>`), decoded [`crate::Instruction`]s,
+//! [`crate::Snapshot`]s, [`crate::TraceEvent`]s, linker [`crate::link::Object`]s and
+//! [`crate::debug::DebugInfo`] are all serializable. These helpers write and read them as JSON.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub fn to_string<T: Serialize>(value: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string(value)
+}
+
+pub fn from_str<T: DeserializeOwned>(json: &str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+pub fn save<T: Serialize, P: AsRef<Path>>(path: P, value: &T) -> io::Result<()> {
+    fs::write(path, serde_json::to_vec(value)?)
+}
+
+pub fn load<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Writes one value per line (JSON Lines), so a long trace can be read back or diffed without
+/// parsing it all as one document.
+pub fn save_lines<T: Serialize, P: AsRef<Path>>(path: P, values: &[T]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    for value in values {
+        serde_json::to_writer(&mut file, value)?;
+        file.write_all(b"\n")?;
+    }
+    file.flush()
+}
+
+/// Reads what [`save_lines`] wrote. Blank lines are skipped.
+pub fn load_lines<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<Vec<T>> {
+    let mut values = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            values.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, Error, Instruction, MachineBuilder, Snapshot, TraceEvent};
+
+    #[test]
+    fn it_round_trips_machine_state() {
+        let program = vec![1002, 4, 3, 4, 33];
+        assert_eq!(to_string(&program).unwrap(), "[1002,4,3,4,33]");
+        assert_eq!(from_str::<Vec<i64>>("[1002,4,3,4,33]").unwrap(), program);
+
+        let instr = decode(1002).unwrap();
+        let json = to_string(&instr).unwrap();
+        assert_eq!(json, r#"{"opcode":2,"mode_op1":0,"mode_op2":1,"mode_op3":0}"#);
+        assert_eq!(from_str::<Instruction>(&json).unwrap(), instr);
+
+        let (_, mut m) = MachineBuilder::new().trace(false).record_trace(true).build(program);
+        m.execute().unwrap();
+        let snapshot = m.snapshot();
+        assert_eq!(from_str::<Snapshot>(&to_string(&snapshot).unwrap()).unwrap(), snapshot);
+        let events = m.take_trace_events();
+        assert_eq!(from_str::<Vec<TraceEvent>>(&to_string(&events).unwrap()).unwrap(), events);
+
+        let err = Error::InvalidInstruction { pc: 4, instruction: 99 };
+        assert_eq!(from_str::<Error>(&to_string(&err).unwrap()).unwrap(), err);
+    }
+
+    #[test]
+    fn it_saves_and_loads_files() {
+        let path = std::env::temp_dir().join(format!("intcode-json-{}.json", std::process::id()));
+        let (_, m) = MachineBuilder::new().build(vec![104, 1, 99]);
+        save(&path, &m.snapshot()).unwrap();
+        let loaded: Snapshot = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, m.snapshot());
+
+        assert_eq!(load::<Snapshot, _>(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let (_, mut m) = MachineBuilder::new().trace(false).record_trace(true).build(vec![104, 1, 99]);
+        m.execute().unwrap();
+        save_lines(&path, m.trace_events()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let loaded: Vec<TraceEvent> = load_lines(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, m.trace_events());
+    }
+}
@@ -0,0 +1,801 @@
+//! A small language compiling to intcode.
+//!
+//! ```text
+//! // sum of the squares of 1..=n
+//! fn main() {
+//!     var n = input();
+//!     var total = 0;
+//!     while (n > 0) {
+//!         total = total + square(n);
+//!         n = n - 1;
+//!     }
+//!     output(total);
+//! }
+//!
+//! fn square(x) {
+//!     return x * x;
+//! }
+//! ```
+//!
+//! Values are integers and anything non-zero is true. There are `+`, `-`, `*`, the comparisons
+//! `<`, `>`, `<=`, `>=`, `==`, `!=` (giving 0 or 1), unary `-` and `!`, `input()` and
+//! `output(x)`. Variables are declared with `var` and live until the end of their function.
+//! Functions return 0 unless they say otherwise; returning from `main` halts.
+//!
+//! The compiler emits assembly for [`crate::asm`]. Every function call gets its own stack frame
+//! addressed through the relative base, following the convention [`crate::decompile`] expects:
+//! `[rb+0]` holds the return address, `[rb+1]` onwards the parameters, then the variables and
+//! temporaries. The result is returned in `[rb+1]`.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+use crate::asm::{self, AsmError};
+
+/// Lines are counted from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompileError {
+    Syntax { line: usize, message: String },
+    UndefinedVariable { line: usize, name: String },
+    UndefinedFunction { line: usize, name: String },
+    /// A second variable or function with the same name.
+    Redefined { line: usize, name: String },
+    ArgumentCount { line: usize, name: String, expected: usize, found: usize },
+    NoMain,
+    /// The generated assembly didn't assemble, which is a bug in the compiler. The line is one of
+    /// [`compile_to_asm`]'s output.
+    Assembly(AsmError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            CompileError::UndefinedVariable { line, name } => write!(f, "line {}: undefined variable {}", line, name),
+            CompileError::UndefinedFunction { line, name } => write!(f, "line {}: undefined function {}", line, name),
+            CompileError::Redefined { line, name } => write!(f, "line {}: {} is already defined", line, name),
+            CompileError::ArgumentCount { line, name, expected, found } => {
+                write!(f, "line {}: {} takes {} arguments, not {}", line, name, expected, found)
+            }
+            CompileError::NoMain => write!(f, "no main function"),
+            CompileError::Assembly(err) => write!(f, "generated assembly: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CompileError {}
+
+/// Compile to an intcode program.
+pub fn compile(src: &str) -> Result<Vec<i64>, CompileError> {
+    assemble(&compile_to_asm(src)?)
+}
+
+fn assemble(asm: &str) -> Result<Vec<i64>, CompileError> {
+    asm::assemble(asm).map(|assembly| assembly.program).map_err(CompileError::Assembly)
+}
+
+/// Compile to assembly, see [`crate::asm`].
+pub fn compile_to_asm(src: &str) -> Result<String, CompileError> {
+    let functions = Parser { tokens: lex(src)?, pos: 0 }.program()?;
+    Codegen::new(&functions)?.program()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: [&str; 17] = ["<=", ">=", "==", "!=", "(", ")", "{", "}", ",", ";", "=", "+", "-", "*", "<", ">", "!"];
+const KEYWORDS: [&str; 8] = ["fn", "var", "if", "else", "while", "return", "input", "output"];
+
+fn lex(src: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = vec![];
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let mut rest = text.split("//").next().unwrap().trim_start();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let num = rest[..len].parse().map_err(|_| CompileError::Syntax {
+                    line,
+                    message: format!("number {} is too large", &rest[..len]),
+                })?;
+                tokens.push((line, Token::Num(num)));
+                len
+            } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                tokens.push((line, Token::Ident(rest[..len].to_string())));
+                len
+            } else {
+                let punct = PUNCTUATION.iter().find(|p| rest.starts_with(*p)).ok_or_else(|| {
+                    CompileError::Syntax { line, message: format!("unexpected {:?}", rest.chars().next().unwrap()) }
+                })?;
+                tokens.push((line, Token::Punct(punct)));
+                punct.len()
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Input,
+    Call { name: String, args: Vec<Expr>, line: usize },
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Output(Expr),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    params: Vec<String>,
+    // declared with `var`, in order
+    locals: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last()).map_or(1, |(line, _)| *line)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError::Syntax { line: self.line(), message })
+    }
+
+    fn found(&self) -> String {
+        match self.peek() {
+            Some(Token::Num(num)) => num.to_string(),
+            Some(Token::Ident(name)) => name.clone(),
+            Some(Token::Punct(punct)) => punct.to_string(),
+            None => "end of input".to_string(),
+        }
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Punct(p)) if *p == punct) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(name)) if name == keyword) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), CompileError> {
+        if !self.eat(punct) {
+            return self.error(format!("expected {}, found {}", punct, self.found()));
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Some(Token::Ident(name)) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => self.error(format!("expected a name, found {}", self.found())),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Function>, CompileError> {
+        let mut functions: Vec<Function> = vec![];
+        while self.peek().is_some() {
+            let line = self.line();
+            if !self.eat_keyword("fn") {
+                return self.error(format!("expected fn, found {}", self.found()));
+            }
+            let name = self.ident()?;
+            if functions.iter().any(|f| f.name == name) {
+                return Err(CompileError::Redefined { line, name });
+            }
+            let mut function = Function { name, params: vec![], locals: vec![], body: vec![] };
+            self.expect("(")?;
+            while !self.eat(")") {
+                if !function.params.is_empty() {
+                    self.expect(",")?;
+                }
+                let line = self.line();
+                let param = self.ident()?;
+                if function.params.contains(&param) {
+                    return Err(CompileError::Redefined { line, name: param });
+                }
+                function.params.push(param);
+            }
+            function.body = self.block(&mut function)?;
+            functions.push(function);
+        }
+        Ok(functions)
+    }
+
+    fn block(&mut self, function: &mut Function) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut body = vec![];
+        while !self.eat("}") {
+            if self.peek().is_none() {
+                return self.error("expected }, found end of input".to_string());
+            }
+            body.push(self.statement(function)?);
+        }
+        Ok(body)
+    }
+
+    fn statement(&mut self, function: &mut Function) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let stmt = if self.eat_keyword("var") {
+            let name = self.ident()?;
+            if function.params.contains(&name) || function.locals.contains(&name) {
+                return Err(CompileError::Redefined { line, name });
+            }
+            self.expect("=")?;
+            let value = self.expr(function)?;
+            function.locals.push(name.clone());
+            Stmt::Assign(name, value)
+        } else if self.eat_keyword("if") {
+            return self.if_statement(function);
+        } else if self.eat_keyword("while") {
+            self.expect("(")?;
+            let cond = self.expr(function)?;
+            self.expect(")")?;
+            return Ok(Stmt::While(cond, self.block(function)?));
+        } else if self.eat_keyword("return") {
+            match self.peek() {
+                Some(Token::Punct(";")) => Stmt::Return(None),
+                _ => Stmt::Return(Some(self.expr(function)?)),
+            }
+        } else if self.eat_keyword("output") {
+            self.expect("(")?;
+            let value = self.expr(function)?;
+            self.expect(")")?;
+            Stmt::Output(value)
+        } else if matches!(self.tokens.get(self.pos + 1), Some((_, Token::Punct("=")))) {
+            let name = self.ident()?;
+            self.variable(function, &name, line)?;
+            self.pos += 1;
+            Stmt::Assign(name, self.expr(function)?)
+        } else {
+            Stmt::Expr(self.expr(function)?)
+        };
+        self.expect(";")?;
+        Ok(stmt)
+    }
+
+    fn if_statement(&mut self, function: &mut Function) -> Result<Stmt, CompileError> {
+        self.expect("(")?;
+        let cond = self.expr(function)?;
+        self.expect(")")?;
+        let then = self.block(function)?;
+        let otherwise = if !self.eat_keyword("else") {
+            vec![]
+        } else if self.eat_keyword("if") {
+            vec![self.if_statement(function)?]
+        } else {
+            self.block(function)?
+        };
+        Ok(Stmt::If(cond, then, otherwise))
+    }
+
+    fn variable(&self, function: &Function, name: &str, line: usize) -> Result<(), CompileError> {
+        if !function.params.iter().chain(&function.locals).any(|var| var == name) {
+            return Err(CompileError::UndefinedVariable { line, name: name.to_string() });
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, function: &Function) -> Result<Expr, CompileError> {
+        let lhs = self.additive(function)?;
+        let ops = [("<=", BinOp::Le), (">=", BinOp::Ge), ("==", BinOp::Eq), ("!=", BinOp::Ne), ("<", BinOp::Lt), (">", BinOp::Gt)];
+        for (punct, op) in ops {
+            if self.eat(punct) {
+                let rhs = self.additive(function)?;
+                return Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn additive(&mut self, function: &Function) -> Result<Expr, CompileError> {
+        let mut lhs = self.term(function)?;
+        loop {
+            let op = if self.eat("+") {
+                BinOp::Add
+            } else if self.eat("-") {
+                BinOp::Sub
+            } else {
+                return Ok(lhs);
+            };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term(function)?));
+        }
+    }
+
+    fn term(&mut self, function: &Function) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary(function)?;
+        while self.eat("*") {
+            lhs = Expr::Binary(BinOp::Mul, Box::new(lhs), Box::new(self.unary(function)?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self, function: &Function) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(match self.unary(function)? {
+                Expr::Num(num) => Expr::Num(num.wrapping_neg()),
+                expr => Expr::Neg(Box::new(expr)),
+            });
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary(function)?)));
+        }
+        self.primary(function)
+    }
+
+    fn primary(&mut self, function: &Function) -> Result<Expr, CompileError> {
+        let line = self.line();
+        if let Some(Token::Num(num)) = self.peek() {
+            let num = *num;
+            self.pos += 1;
+            return Ok(Expr::Num(num));
+        }
+        if self.eat("(") {
+            let expr = self.expr(function)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat_keyword("input") {
+            self.expect("(")?;
+            self.expect(")")?;
+            return Ok(Expr::Input);
+        }
+        let name = self.ident()?;
+        if !self.eat("(") {
+            self.variable(function, &name, line)?;
+            return Ok(Expr::Var(name));
+        }
+        let mut args = vec![];
+        while !self.eat(")") {
+            if !args.is_empty() {
+                self.expect(",")?;
+            }
+            args.push(self.expr(function)?);
+        }
+        Ok(Expr::Call { name, args, line })
+    }
+}
+
+struct Codegen<'a> {
+    functions: &'a [Function],
+    asm: String,
+    labels: usize,
+    // the function being compiled
+    function: &'a Function,
+    slots: BTreeMap<&'a str, i64>,
+    first_temp: i64,
+    next_temp: i64,
+    frame: i64,
+}
+
+impl<'a> Codegen<'a> {
+    fn new(functions: &'a [Function]) -> Result<Self, CompileError> {
+        let main = functions.iter().find(|f| f.name == "main").ok_or(CompileError::NoMain)?;
+        Ok(Codegen {
+            functions,
+            asm: String::new(),
+            labels: 0,
+            function: main,
+            slots: BTreeMap::new(),
+            first_temp: 0,
+            next_temp: 0,
+            frame: 0,
+        })
+    }
+
+    fn program(mut self) -> Result<String, CompileError> {
+        // main first, so that it starts at address 0 by setting up the stack
+        self.emit("ARB #.stack");
+        let main = self.function;
+        self.emit_function(main)?;
+        for function in self.functions.iter().filter(|f| f.name != "main") {
+            self.emit_function(function)?;
+        }
+        writeln!(self.asm, ".stack:").unwrap();
+        Ok(self.asm)
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        writeln!(self.asm, "    {}", instruction).unwrap();
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("{}.{}", self.function.name, self.labels)
+    }
+
+    fn temp(&mut self) -> String {
+        let slot = self.next_temp;
+        self.next_temp += 1;
+        self.frame = self.frame.max(self.next_temp);
+        format!("[rb+{}]", slot)
+    }
+
+    fn emit_function(&mut self, function: &'a Function) -> Result<(), CompileError> {
+        self.function = function;
+        // slot 1 holds the result even without parameters
+        let locals = (function.params.len() as i64).max(1) + 1;
+        self.slots = function.params.iter().enumerate().map(|(i, name)| (name.as_str(), i as i64 + 1)).collect();
+        self.slots.extend(function.locals.iter().enumerate().map(|(i, name)| (name.as_str(), locals + i as i64)));
+        self.first_temp = locals + function.locals.len() as i64;
+        self.frame = self.first_temp;
+
+        writeln!(self.asm, "{}:", function.name).unwrap();
+        self.block(&function.body)?;
+        self.ret(None)?;
+        writeln!(self.asm, "{}.frame = {}", function.name, self.frame).unwrap();
+        Ok(())
+    }
+
+    fn block(&mut self, body: &'a [Stmt]) -> Result<(), CompileError> {
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn ret(&mut self, value: Option<&'a Expr>) -> Result<(), CompileError> {
+        if self.function.name == "main" {
+            self.emit("HALT");
+            return Ok(());
+        }
+        let value = match value {
+            Some(value) => self.expr(value)?,
+            None => "#0".to_string(),
+        };
+        self.emit(&format!("ADD {} #0 [rb+1]", value));
+        self.emit("JT #1 [rb+0]");
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &'a Stmt) -> Result<(), CompileError> {
+        // temporaries only live for one statement
+        self.next_temp = self.first_temp;
+        match stmt {
+            Stmt::Assign(name, value) => {
+                let value = self.expr(value)?;
+                self.emit(&format!("ADD {} #0 [rb+{}]", value, self.slots[name.as_str()]));
+            }
+            Stmt::If(cond, then, otherwise) => {
+                let cond = self.expr(cond)?;
+                let (otherwise_label, end) = (self.label(), self.label());
+                self.emit(&format!("JF {} #{}", cond, otherwise_label));
+                self.block(then)?;
+                if !otherwise.is_empty() {
+                    self.emit(&format!("JT #1 #{}", end));
+                }
+                writeln!(self.asm, "{}:", otherwise_label).unwrap();
+                self.block(otherwise)?;
+                writeln!(self.asm, "{}:", end).unwrap();
+            }
+            Stmt::While(cond, body) => {
+                let (top, end) = (self.label(), self.label());
+                writeln!(self.asm, "{}:", top).unwrap();
+                let cond = self.expr(cond)?;
+                self.emit(&format!("JF {} #{}", cond, end));
+                self.block(body)?;
+                self.emit(&format!("JT #1 #{}", top));
+                writeln!(self.asm, "{}:", end).unwrap();
+            }
+            Stmt::Output(value) => {
+                let value = self.expr(value)?;
+                self.emit(&format!("OUT {}", value));
+            }
+            Stmt::Return(value) => self.ret(value.as_ref())?,
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    // an operand holding the value of `expr`
+    fn expr(&mut self, expr: &'a Expr) -> Result<String, CompileError> {
+        Ok(match expr {
+            Expr::Num(num) => format!("#{}", num),
+            Expr::Var(name) => format!("[rb+{}]", self.slots[name.as_str()]),
+            Expr::Input => {
+                let result = self.temp();
+                self.emit(&format!("IN {}", result));
+                result
+            }
+            Expr::Neg(expr) => {
+                let value = self.expr(expr)?;
+                let result = self.temp();
+                self.emit(&format!("MULT {} #-1 {}", value, result));
+                result
+            }
+            Expr::Not(expr) => {
+                let value = self.expr(expr)?;
+                let result = self.temp();
+                self.emit(&format!("EQ {} #0 {}", value, result));
+                result
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (self.expr(lhs)?, self.expr(rhs)?);
+                let result = self.temp();
+                let (mnemonic, a, b, negate) = match op {
+                    BinOp::Add => ("ADD", a, b, false),
+                    BinOp::Sub => match **rhs {
+                        Expr::Num(num) => ("ADD", a, format!("#{}", num.wrapping_neg()), false),
+                        _ => {
+                            let negated = self.temp();
+                            self.emit(&format!("MULT {} #-1 {}", b, negated));
+                            ("ADD", a, negated, false)
+                        }
+                    },
+                    BinOp::Mul => ("MULT", a, b, false),
+                    BinOp::Lt => ("LT", a, b, false),
+                    BinOp::Gt => ("LT", b, a, false),
+                    BinOp::Le => ("LT", b, a, true),
+                    BinOp::Ge => ("LT", a, b, true),
+                    BinOp::Eq => ("EQ", a, b, false),
+                    BinOp::Ne => ("EQ", a, b, true),
+                };
+                self.emit(&format!("{} {} {} {}", mnemonic, a, b, result));
+                if negate {
+                    self.emit(&format!("EQ {} #0 {}", result, result));
+                }
+                result
+            }
+            Expr::Call { name, args, line } => self.call(name, args, *line)?,
+        })
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expr], line: usize) -> Result<String, CompileError> {
+        let callee = self
+            .functions
+            .iter()
+            .find(|f| f.name == name)
+            .ok_or_else(|| CompileError::UndefinedFunction { line, name: name.to_string() })?;
+        if callee.params.len() != args.len() {
+            return Err(CompileError::ArgumentCount {
+                line,
+                name: name.to_string(),
+                expected: callee.params.len(),
+                found: args.len(),
+            });
+        }
+        // arguments can contain calls themselves, so work them all out before setting up the frame
+        let values = args.iter().map(|arg| self.expr(arg)).collect::<Result<Vec<_>, _>>()?;
+        let frame = format!("{}.frame", self.function.name);
+        let ret = self.label();
+        self.emit(&format!("ADD #{} #0 [rb+{}]", ret, frame));
+        for (i, value) in values.iter().enumerate() {
+            self.emit(&format!("ADD {} #0 [rb+{}+{}]", value, frame, i + 1));
+        }
+        self.emit(&format!("ARB #{}", frame));
+        self.emit(&format!("JT #1 #{}", name));
+        writeln!(self.asm, "{}:", ret).unwrap();
+        self.emit(&format!("ARB #-{}", frame));
+        let result = self.temp();
+        self.emit(&format!("ADD [rb+{}+1] #0 {}", frame, result));
+        Ok(result)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::decompile::decompile;
+    use crate::{MachineBuilder, Status};
+
+    fn run(src: &str, input: &[i64]) -> Vec<i64> {
+        let program = compile(src).unwrap();
+        let (_, mut m) = MachineBuilder::new().trace(false).instruction_budget(1_000_000).build(program);
+        m.set_input_string(input.iter().map(|val| format!("{}\n", val)).collect());
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        m.get_output().clone()
+    }
+
+    const SUM_OF_SQUARES: &str = "
+// sum of the squares of 1..=n
+fn main() {
+    var n = input();
+    var total = 0;
+    while (n > 0) {
+        total = total + square(n);
+        n = n - 1;
+    }
+    output(total);
+}
+
+fn square(x) {
+    return x * x;
+}
+";
+
+    const FACTORIAL: &str = "
+fn main() {
+    output(factorial(input()));
+}
+
+fn factorial(n) {
+    if (n <= 1) {
+        return 1;
+    }
+    return n * factorial(n - 1);
+}
+";
+
+    const COMPARE: &str = "
+// the sign of a - b, then the truth table of a few operators
+fn main() {
+    var a = input();
+    var b = input();
+    if (a < b) {
+        output(-1);
+    } else if (a == b) {
+        output(0);
+    } else {
+        output(1);
+    }
+    output(a != b);
+    output(a >= b);
+    output(!(a > b));
+    output(-(a - b));
+}
+";
+
+    const FIBONACCI: &str = "
+fn main() {
+    var i = 0;
+    var n = input();
+    while (i < n) {
+        output(fib(i, 0, 1));
+        i = i + 1;
+    }
+}
+
+// nth fibonacci number, counting on from a and b
+fn fib(n, a, b) {
+    if (n == 0) {
+        return a;
+    }
+    return fib(n - 1, b, a + b);
+}
+";
+
+    #[test]
+    fn it_runs_example_programs() {
+        assert_eq!(run(SUM_OF_SQUARES, &[10]), vec![385]);
+        assert_eq!(run(SUM_OF_SQUARES, &[0]), vec![0]);
+        assert_eq!(run(FACTORIAL, &[10]), vec![3628800]);
+        assert_eq!(run(COMPARE, &[3, 5]), vec![-1, 1, 0, 1, 2]);
+        assert_eq!(run(COMPARE, &[5, 5]), vec![0, 0, 1, 1, 0]);
+        assert_eq!(run(COMPARE, &[7, 5]), vec![1, 1, 1, 0, -2]);
+        assert_eq!(run(FIBONACCI, &[10]), vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+    }
+
+    #[test]
+    fn it_emits_assembly() {
+        let asm = compile_to_asm("fn main() {\n    output(double(21));\n}\n\nfn double(x) {\n    return x + x;\n}\n").unwrap();
+        assert_eq!(asm, "    ARB #.stack
+main:
+    ADD #main.1 #0 [rb+main.frame]
+    ADD #21 #0 [rb+main.frame+1]
+    ARB #main.frame
+    JT #1 #double
+main.1:
+    ARB #-main.frame
+    ADD [rb+main.frame+1] #0 [rb+2]
+    OUT [rb+2]
+    HALT
+main.frame = 3
+double:
+    ADD [rb+1] [rb+1] [rb+2]
+    ADD [rb+2] #0 [rb+1]
+    JT #1 [rb+0]
+    ADD #0 #0 [rb+1]
+    JT #1 [rb+0]
+double.frame = 3
+.stack:
+");
+        // the decompiler knows the calling convention
+        assert_eq!(decompile(&compile(FACTORIAL).unwrap()), "\
+fn main() {
+    // stack frames start at 85
+    l2 = input();
+    l5 = f26(l2);
+    l3 = l5;
+    output(l3);
+    halt;
+}
+
+fn f26(p1) {
+    l2 = 1 < p1;
+    l2 = l2 == 0;
+    if (l2) {
+        p1 = 1;
+        return p1;
+    }
+    l2 = p1 - 1;
+    l6 = f26(l2);
+    l3 = l6;
+    l4 = p1 * l3;
+    p1 = l4;
+    return p1;
+}
+");
+    }
+
+    #[test]
+    fn it_reports_errors() {
+        let err = |src| compile(src).unwrap_err();
+        assert_eq!(err("fn helper() {}"), CompileError::NoMain);
+        assert_eq!(err("fn main() {\n    output(x);\n}"), CompileError::UndefinedVariable { line: 2, name: "x".to_string() });
+        assert_eq!(err("fn main() {\n    f();\n}"), CompileError::UndefinedFunction { line: 2, name: "f".to_string() });
+        assert_eq!(
+            err("fn main() { f(1); }\nfn f(a, b) {}"),
+            CompileError::ArgumentCount { line: 1, name: "f".to_string(), expected: 2, found: 1 }
+        );
+        assert_eq!(err("fn main() {\n    var a = 1;\n    var a = 2;\n}"), CompileError::Redefined { line: 3, name: "a".to_string() });
+        assert_eq!(err("fn main() {\n    output(1)\n}"), CompileError::Syntax { line: 3, message: "expected ;, found }".to_string() });
+        assert_eq!(err("fn main() { output(1 / 2); }"), CompileError::Syntax { line: 1, message: "unexpected '/'".to_string() });
+        // a code generator bug comes out as an error, not a panic
+        assert_eq!(
+            assemble("ADD #1"),
+            Err(CompileError::Assembly(AsmError::OperandCount { line: 1, expected: 3, found: 1 }))
+        );
+    }
+}
@@ -1,208 +1,559 @@
-use std::collections::VecDeque;
+//! An intcode computer.
+//!
+//! The interpreter core only needs `alloc`. The default `std` feature adds output channels,
+//! blocking input, stdin, threads and tracing to stdout; without it the crate is `no_std`, output
+//! is only collected in [`Machine::get_output`] and input comes from [`InputType::Values`].
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(clippy::needless_arbitrary_self_type)]
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::io::{self, BufRead};
-use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+#[cfg(feature = "std")]
+use std::sync::mpsc::{Receiver, Sender, TrySendError};
+
+#[cfg(feature = "std")]
+use builder::OutputSender;
 
-trait ReadLine<T> {
-    fn read_line(&self, buf: &mut String) -> std::io::Result<usize>;
+use extension::{Control, Extension, Param};
+
+pub use builder::{InputExhausted, MachineBuilder, OutputPolicy};
+pub use snapshot::Snapshot;
+pub use trace::{first_divergence, format_divergence, TraceEvent};
+pub use word::Word;
+
+// println! prefixed with the thread id, only when the machine has tracing turned on
+#[cfg(feature = "std")]
+macro_rules! trace {
+    ($machine:expr, $($arg:tt)*) => {
+        if $machine.trace {
+            println!("tid {:?}: {}", thread_id(), format_args!($($arg)*));
+        }
+    };
 }
 
+// nowhere to print to
+#[cfg(not(feature = "std"))]
+macro_rules! trace {
+    ($machine:expr, $($arg:tt)*) => {
+        let _ = (&$machine.trace, format_args!($($arg)*));
+    };
+}
+
+pub mod aot;
+pub mod asm;
+mod builder;
+pub mod cfg;
+mod compiled;
+pub mod conformance;
+pub mod debug;
+pub mod decompile;
+pub mod extension;
+pub mod heatmap;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod lang;
+pub mod link;
+#[cfg(feature = "std")]
+pub mod search;
+pub mod optimize;
+mod snapshot;
+pub mod symbolic;
+mod trace;
+pub mod word;
+
 #[derive(Debug)]
-pub enum InputType {
+pub enum InputType<W = i64> {
+    #[cfg(feature = "std")]
     StringCursor(io::Cursor<String>),
-    IntReceiver(Receiver<i64>),
+    #[cfg(feature = "std")]
+    IntReceiver(Receiver<W>),
+    /// Prompt for values on the terminal until stdin is closed. Meant for command line tools;
+    /// nothing reads stdin unless this is added explicitly.
+    #[cfg(feature = "std")]
+    Stdin,
+    /// Values read one at a time, as they are.
+    Values(VecDeque<W>),
+}
+
+/// Why [`Machine::execute`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Status {
+    /// The program hit a halt instruction.
+    Halted,
+    /// The program wants input and there is none. Add some and call `execute` again to resume.
+    NeedsInput,
 }
 
+/// An intcode computer whose memory cells are `W`. See [`Word`] for the available widths.
 #[derive(Debug)]
-pub struct Machine {
-    memory: Vec<i64>,
+pub struct Machine<W = i64> {
+    memory: Vec<W>,
     program_counter: i64,
-    input: VecDeque<InputType>,
-    output_tx: Option<SyncSender<i64>>,
-    output: Vec<i64>,
+    // added to mode 2 (relative) parameters, moved by opcode 9
+    relative_base: i64,
+    input: VecDeque<InputType<W>>,
+    #[cfg(feature = "std")]
+    output_tx: Option<OutputSender<W>>,
+    output: Vec<W>,
+    #[cfg(feature = "std")]
+    output_policy: OutputPolicy,
+    outputs_lost: u64,
+    detect_self_modification: bool,
+    // address -> start of the most recent instruction executed that covered it
+    executed: BTreeMap<usize, usize>,
+    self_modifications: Vec<SelfModification<W>>,
+    code_modified: bool,
+    extensions: BTreeMap<i32, Extension<W>>,
+    lenient_modes: bool,
+    checked_arithmetic: bool,
+    input_exhausted: InputExhausted,
+    #[cfg(feature = "std")]
+    blocking_input: Option<(Sender<W>, Receiver<W>)>,
+    trace: bool,
+    instruction_budget: Option<u64>,
+    instructions_executed: u64,
+    // None unless recording, see MachineBuilder::record_trace
+    trace_events: Option<Vec<TraceEvent<W>>>,
+    current_event: Option<TraceEvent<W>>,
+    // labels and source lines for the trace, see MachineBuilder::debug_info
+    debug_info: Option<debug::DebugInfo>,
+    // pre-decoded instructions by address, see Machine::execute_compiled
+    compiled: Vec<Option<compiled::Op>>,
 }
 
-#[derive(Debug)]
-struct Instruction {
-    length: usize,
-    opcode: i32,
-    mode_op1: i32,
-    mode_op2: i32,
-    mode_op3: i32, // basically do not use
+/// Why a machine stopped before reaching a halt instruction. Values too wide for an i64 are
+/// reported clamped to the i64 range.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Error {
+    /// The word at `pc` is not an instruction the machine knows.
+    InvalidInstruction { pc: i64, instruction: i64 },
+    /// Parameter `param` (counting from 1) has a mode the machine does not know.
+    InvalidMode { pc: i64, param: usize, mode: i32 },
+    /// Parameter `param` (counting from 1) is written to but is in immediate mode.
+    ImmediateWrite { pc: i64, param: usize },
+    /// The instruction at `pc` needs input and there is none left.
+    InputExhausted { pc: i64 },
+    /// The instruction at `pc` read input that isn't a number.
+    InvalidInput { pc: i64, input: String },
+    /// The instruction budget ran out before reaching the instruction at `pc`.
+    BudgetExhausted { pc: i64 },
+    /// The output instruction at `pc` found the output channel full.
+    OutputFull { pc: i64 },
+    /// The output instruction at `pc` found the output receiver gone.
+    OutputDisconnected { pc: i64 },
+    /// `addr` is outside of memory. `pc` is where the machine was when it was accessed.
+    InvalidAddress { pc: i64, addr: i64 },
+    /// The arithmetic instruction at `pc` overflowed. Only reported with checked arithmetic, see
+    /// [`MachineBuilder::checked_arithmetic`].
+    Overflow { pc: i64 },
+}
+
+impl Error {
+    /// Where the machine was when it stopped.
+    pub fn pc(&self) -> i64 {
+        match self {
+            Error::InvalidInstruction { pc, .. }
+            | Error::InvalidMode { pc, .. }
+            | Error::ImmediateWrite { pc, .. }
+            | Error::InputExhausted { pc }
+            | Error::InvalidInput { pc, .. }
+            | Error::BudgetExhausted { pc }
+            | Error::OutputFull { pc }
+            | Error::OutputDisconnected { pc }
+            | Error::InvalidAddress { pc, .. }
+            | Error::Overflow { pc } => *pc,
+        }
+    }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidInstruction { pc, instruction } => {
+                write!(f, "invalid instruction {} at pc {}", instruction, pc)
+            }
+            Error::InvalidMode { pc, param, mode } => {
+                write!(f, "invalid mode {} for parameter {} at pc {}", mode, param, pc)
+            }
+            Error::ImmediateWrite { pc, param } => {
+                write!(f, "parameter {} at pc {} is written to but in immediate mode", param, pc)
+            }
+            Error::InputExhausted { pc } => write!(f, "out of input at pc {}", pc),
+            Error::InvalidInput { pc, input } => write!(f, "invalid input {:?} at pc {}", input, pc),
+            Error::BudgetExhausted { pc } => write!(f, "instruction budget exhausted at pc {}", pc),
+            Error::OutputFull { pc } => write!(f, "output channel full at pc {}", pc),
+            Error::OutputDisconnected { pc } => write!(f, "output receiver dropped at pc {}", pc),
+            Error::InvalidAddress { pc, addr } => write!(f, "invalid address {} at pc {}", addr, pc),
+            Error::Overflow { pc } => write!(f, "arithmetic overflow at pc {}", pc),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// A write that landed on memory previously executed as part of an instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SelfModification<W = i64> {
+    /// Address of the instruction doing the write.
+    pub pc: i64,
+    /// Address written to.
+    pub target: usize,
+    /// Start address of the executed instruction `target` belongs to.
+    pub instruction: usize,
+    /// Contents of `target` before and after the write.
+    pub before: W,
+    pub after: W,
+    /// Opcode of the affected instruction before and after the write.
+    pub opcode_before: i32,
+    pub opcode_after: i32,
+}
+
+/// An instruction word split into its opcode and parameter modes, see [`decode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instruction {
+    pub opcode: i32,
+    pub mode_op1: i32,
+    pub mode_op2: i32,
+    pub mode_op3: i32,
+}
+
+#[cfg(feature = "std")]
 impl Machine {
     pub fn new(memory: Vec<i64>) -> (Receiver<i64>, Self) {
-        // "available memory should be much larger than the initial program"
-        // I'm also setting an upper bount on size at 2^30 for memory reasons
-        let mut extended_mem = vec![0; (memory.len() * 1024).min(1073741824)];
-        for (i, val) in memory.iter().enumerate() {
-            extended_mem[i] = *val;
-        }
-        let (tx, rx) = sync_channel(1024);
-        (rx, Machine {
-            memory: extended_mem,
-            program_counter: 0,
-            input: VecDeque::new(),
-            output_tx: Some(tx),
-            output: vec![],
-        })
+        MachineBuilder::new().build(memory)
     }
+}
 
-    pub fn execute(self: &mut Self) {
+impl<W: Word> Machine<W> {
+    /// Run until the program halts or needs input.
+    pub fn execute(self: &mut Self) -> Result<Status, Error> {
         loop {
-            let instr = self.get_instr_and_modes();
-            println!("tid {:?}: Instr: {:?}", thread_id(), instr);
-            match instr.opcode {
-                99 => {
-                    println!("tid {:?}: HALT", thread_id());
-                    self.output_tx = None;
-                    break;
-                }
-                1 => {
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    let out_reg = self.load(self.program_counter + 3);
-                    println!("tid {:?}: ADD {} {} into {}", thread_id(), op1, op2, out_reg);
-
-                    self.store(out_reg, op1 + op2);
-                }
-                2 => {
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    let out_reg = self.load(self.program_counter + 3);
-                    println!("tid {:?}: MULT {} {} into {}", thread_id(), op1, op2, out_reg);
+            if let Some(status) = self.step()? {
+                return Ok(status);
+            }
+        }
+    }
 
-                    self.store(out_reg, op1 * op2);
-                }
-                3 => {
-                    //assert!(mode_op1 == 1);
-                    let op1_addr = self.load(self.program_counter + 1);
-                    let line = self.get_input();
-                    println!("tid {:?}: STORE_INPUT {} to {}", thread_id(), line, op1_addr);
+    /// Execute a single instruction. Returns the status if the machine can't go any further,
+    /// None if there is more to run.
+    pub fn step(&mut self) -> Result<Option<Status>, Error> {
+        if self.instruction_budget.is_some_and(|budget| self.instructions_executed >= budget) {
+            return Err(Error::BudgetExhausted { pc: self.program_counter });
+        }
+        if self.trace_events.is_some() {
+            self.begin_event();
+        }
+        let result = self.execute_instruction();
+        if let Err(e) = &result {
+            trace!(self, "ERROR: {}", self.describe_error(e));
+        }
+        if let Some(event) = self.current_event.take() {
+            // instructions that failed or are waiting for input didn't happen
+            if let Ok(None) | Ok(Some(Status::Halted)) = result {
+                self.trace_events.as_mut().unwrap().push(event);
+            }
+        }
+        result
+    }
 
-                    self.store(op1_addr, line.trim().parse::<i64>().unwrap());
-                }
-                4 => {
-                    let val = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    println!("tid {:?}: OUTPUT val: {}", thread_id(), val);
-
-                    self.output.push(val);
-                    match &self.output_tx {
-                        Some(tx) => tx.send(val).unwrap_or(()),
-                        None => panic!("missing output tx"),
-                    }
+    fn execute_instruction(&mut self) -> Result<Option<Status>, Error> {
+        self.instructions_executed += 1;
+        let word = self.load(self.program_counter)?.to_i64_saturating();
+        let instr = decode(word).ok_or(Error::InvalidInstruction { pc: self.program_counter, instruction: word })?;
+        match &self.debug_info {
+            Some(debug) if self.trace => {
+                trace!(self, "Instr: {:?} at {}", instr, debug.describe(self.program_counter));
+            }
+            _ => {
+                trace!(self, "Instr: {:?}", instr);
+            }
+        }
+        self.check_modes(&instr, word)?;
+        if self.detect_self_modification {
+            let start = self.program_counter as usize;
+            let len = self.instruction_length(instr.opcode).unwrap();
+            for addr in start..start + len {
+                self.executed.insert(addr, start);
+            }
+        }
+        if self.extensions.contains_key(&instr.opcode) {
+            let modes = [instr.mode_op1, instr.mode_op2, instr.mode_op3];
+            match self.execute_extension(instr.opcode, modes)? {
+                Control::Continue => {
+                    self.program_counter += self.instruction_length(instr.opcode).unwrap() as i64;
                 }
-                5 => {  // jump-if-true
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    if op1 != 0 {
-                        self.program_counter = op2;
-                        continue; // we don't want to increment the PC like normal
-                    }
+                Control::Jump(target) => self.program_counter = target,
+                Control::Halt => {
+                    trace!(self, "HALT");
+                    self.halt();
+                    return Ok(Some(Status::Halted));
                 }
-                6 => {  // jump-if-false
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    if op1 == 0 {
-                        self.program_counter = op2;
-                        continue; // we don't want to increment the PC like normal
-                    }
+            }
+            return Ok(None);
+        }
+        match instr.opcode {
+            99 => {
+                trace!(self, "HALT");
+                self.halt();
+                return Ok(Some(Status::Halted));
+            }
+            1 => {
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                let out_reg = self.write_address(self.program_counter + 3, instr.mode_op3)?;
+                trace!(self, "ADD {} {} into {}", op1, op2, out_reg);
+
+                let sum = self.add(&op1, &op2)?;
+                self.store(out_reg, sum)?;
+            }
+            2 => {
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                let out_reg = self.write_address(self.program_counter + 3, instr.mode_op3)?;
+                trace!(self, "MULT {} {} into {}", op1, op2, out_reg);
+
+                let product = self.mul(&op1, &op2)?;
+                self.store(out_reg, product)?;
+            }
+            3 => {
+                let op1_addr = self.write_address(self.program_counter + 1, instr.mode_op1)?;
+                let val = match self.read_input()? {
+                    Some(val) => val,
+                    None => return Ok(Some(self.pause_for_input())),
+                };
+                trace!(self, "STORE_INPUT {} to {}", val, op1_addr);
+
+                self.store(op1_addr, val)?;
+            }
+            4 => {
+                let val = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                trace!(self, "OUTPUT val: {}", val);
+
+                self.emit_output(val)?;
+            }
+            5 => {  // jump-if-true
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                if !op1.is_zero() {
+                    self.program_counter = op2.to_i64_saturating();
+                    return Ok(None); // we don't want to increment the PC like normal
                 }
-                7 => {  // less-than
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    let out_reg = self.load(self.program_counter + 3);
-                    if op1 < op2 {
-                        self.store(out_reg, 1);
-                    } else {
-                        self.store(out_reg, 0);
-                    }
+            }
+            6 => {  // jump-if-false
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                if op1.is_zero() {
+                    self.program_counter = op2.to_i64_saturating();
+                    return Ok(None); // we don't want to increment the PC like normal
                 }
-                8 => {  // equals
-                    let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1);
-                    let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2);
-                    let out_reg = self.load(self.program_counter + 3);
-                    if op1 == op2 {
-                        self.store(out_reg, 1);
-                    } else {
-                        self.store(out_reg, 0);
-                    }
+            }
+            7 => {  // less-than
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                let out_reg = self.write_address(self.program_counter + 3, instr.mode_op3)?;
+                if op1 < op2 {
+                    self.store(out_reg, W::from_i64(1))?;
+                } else {
+                    self.store(out_reg, W::from_i64(0))?;
                 }
-                _ => {
-                    panic!("something broke!");
+            }
+            8 => {  // equals
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                let op2 = self.load_with_mode(self.program_counter + 2, instr.mode_op2)?;
+                let out_reg = self.write_address(self.program_counter + 3, instr.mode_op3)?;
+                if op1 == op2 {
+                    self.store(out_reg, W::from_i64(1))?;
+                } else {
+                    self.store(out_reg, W::from_i64(0))?;
                 }
             }
-
-            // println!("{:?}", self);
-            self.program_counter += match instr.opcode {
-                1|2|7|8 => 4,
-                3|4 => 2,
-                5|6 => 3,
-                _ => unreachable!("invalid opcode")
+            9 => {  // adjust relative base
+                let op1 = self.load_with_mode(self.program_counter + 1, instr.mode_op1)?;
+                self.relative_base = self.relative_base.saturating_add(op1.to_i64_saturating());
+                trace!(self, "ADJUST_BASE by {} to {}", op1, self.relative_base);
             }
+            _ => unreachable!("opcode checked by check_modes"),
+        }
+
+        // println!("{:?}", self);
+        self.program_counter += instruction_length(instr.opcode).unwrap() as i64;
+        Ok(None)
+    }
+
+    fn add(&self, a: &W, b: &W) -> Result<W, Error> {
+        match self.checked_arithmetic {
+            true => a.checked_add(b).ok_or(Error::Overflow { pc: self.program_counter }),
+            false => Ok(a.wrapping_add(b)),
         }
+    }
 
+    fn mul(&self, a: &W, b: &W) -> Result<W, Error> {
+        match self.checked_arithmetic {
+            true => a.checked_mul(b).ok_or(Error::Overflow { pc: self.program_counter }),
+            false => Ok(a.wrapping_mul(b)),
+        }
     }
 
-    pub fn execute_async(mut self: Self) -> std::thread::JoinHandle<()> {
+    // lets the output receiver know nothing more is coming
+    fn halt(&mut self) {
+        #[cfg(feature = "std")]
+        {
+            self.output_tx = None;
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn execute_async(mut self: Self) -> std::thread::JoinHandle<Result<Status, Error>> {
         std::thread::spawn(move || {
-            self.execute();
+            self.execute()
         })
     }
 
-    // ABCDE
-    //  1002
+    /// Accept any mode on parameters that are written to, treating everything but relative mode
+    /// as position mode like earlier versions of the machine did. By default an immediate-mode
+    /// write is an error.
+    pub fn set_lenient_modes(&mut self, lenient: bool) {
+        self.lenient_modes = lenient;
+        self.compiled.clear();
+    }
 
-    // DE - two-digit opcode,      02 == opcode 2
-    // C - mode of 1st parameter,  0 == position mode
-    // B - mode of 2nd parameter,  1 == immediate mode
-    // A - mode of 3rd parameter,  0 == position mode,
-       //                               omitted due to being a leading zero
-    // Parameters that an instruction writes to will never be in immediate mode.
-    // return opcode first and return modes as items 1, 2, 3 of tuple
-    fn get_instr_and_modes(self: &Self) -> Instruction {
+    fn check_modes(&self, instr: &Instruction, word: i64) -> Result<(), Error> {
+        let pc = self.program_counter;
+        let params = match self.extensions.get(&instr.opcode) {
+            Some(ext) => &ext.params[..],
+            None => builtin_params(instr.opcode).ok_or(Error::InvalidInstruction { pc, instruction: word })?,
+        };
+        let modes = [instr.mode_op1, instr.mode_op2, instr.mode_op3];
+        for (i, (param, &mode)) in params.iter().zip(modes.iter()).enumerate() {
+            match (param, mode) {
+                (Param::Write, _) if self.lenient_modes => {}
+                (Param::Write, 1) => return Err(Error::ImmediateWrite { pc, param: i + 1 }),
+                (_, 0) | (Param::Read, 1) | (_, 2) => {}
+                (_, mode) => return Err(Error::InvalidMode { pc, param: i + 1, mode }),
+            }
+        }
+        Ok(())
+    }
 
-        let instr = self.load(self.program_counter);
-        let digits_str = instr.to_string();
-        let digits = (0..digits_str.len()).map(|i| digits_str.get(i..i+1).unwrap().parse::<i32>().unwrap()).collect::<Vec<i32>>();
-        // println!("{:?}", digits);
+    /// The value at address 0, where day 2 programs leave their result.
+    pub fn output(self: &Self) -> Result<W, Error> {
+        self.load(0)
+    }
 
-        match digits_str.len() {
-            1 => {
-                Instruction { length: digits.len(), opcode: digits[0], mode_op1: 0, mode_op2: 0, mode_op3: 0}
-            }
-            2 => {
-                Instruction { length: digits.len(), opcode: digits[0] * 10 + digits[1], mode_op1: 0, mode_op2: 0, mode_op3: 0}
-            }
-            3 => {
-                Instruction { length: digits.len(), opcode: digits[1] * 10 + digits[2], mode_op1: digits[0], mode_op2: 0, mode_op3: 0}
-            }
-            4 => {
-                Instruction { length: digits.len(), opcode: digits[2] * 10 + digits[3], mode_op1: digits[1], mode_op2: digits[0], mode_op3: 0}
-            }
-            5 => {
-                unreachable!("theoretically possible but shouldn't happen because param 3 should never be in immediate mode: {:?}", digits);
-            }
-            _ => {
-                unreachable!("input integer too long: {:?}", digits);
-            }
+    // The input instruction at the program counter found no input: it isn't counted as executed
+    // and runs again on resume.
+    fn pause_for_input(&mut self) -> Status {
+        self.instructions_executed -= 1;
+        Status::NeedsInput
+    }
+
+    // None when execution should pause until more input is added
+    fn read_input(&mut self) -> Result<Option<W>, Error> {
+        let val = self.get_input()?;
+        if let (Some(event), Some(val)) = (&mut self.current_event, &val) {
+            event.input = Some(val.clone());
         }
+        Ok(val)
     }
 
-    pub fn output(self: &Self) -> i64 {
-        self.memory[0]
+    #[cfg(feature = "std")]
+    fn parse_input(&self, input: String) -> Result<W, Error> {
+        match W::parse(input.trim()) {
+            Some(val) => Ok(val),
+            None => Err(Error::InvalidInput { pc: self.program_counter, input }),
+        }
     }
 
-    fn get_input(&mut self) -> String {
+    /// For machines built with [`InputExhausted::Block`]: values sent here are read once every
+    /// other input source is used up.
+    #[cfg(feature = "std")]
+    pub fn input_sender(&self) -> Option<Sender<W>> {
+        self.blocking_input.as_ref().map(|(tx, _)| tx.clone())
+    }
+
+    /// Number of instructions executed so far.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    fn emit_output(&mut self, val: W) -> Result<(), Error> {
+        if let Some(event) = &mut self.current_event {
+            event.output = Some(val.clone());
+        }
+        self.output.push(val.clone());
+        #[cfg(feature = "std")]
+        self.send_output(val)?;
+        Ok(())
+    }
+
+    // machines built without an output channel only keep output in `output`
+    #[cfg(feature = "std")]
+    fn send_output(&mut self, val: W) -> Result<(), Error> {
+        let pc = self.program_counter;
+        let tx = match &self.output_tx {
+            Some(tx) => tx,
+            None => return Ok(()),
+        };
+        let sent = match self.output_policy {
+            OutputPolicy::Block | OutputPolicy::Unbounded => tx.send(val).is_ok(),
+            OutputPolicy::Drop => tx.try_send(val).is_ok(),
+            OutputPolicy::Error => match tx.try_send(val) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => return Err(Error::OutputFull { pc }),
+                Err(TrySendError::Disconnected(_)) => return Err(Error::OutputDisconnected { pc }),
+            },
+        };
+        if !sent {
+            self.outputs_lost += 1;
+        }
+        Ok(())
+    }
+
+    /// Number of outputs that never made it into the output channel, either because the
+    /// receiver was dropped or because [`OutputPolicy::Drop`] threw them away.
+    pub fn outputs_lost(&self) -> u64 {
+        self.outputs_lost
+    }
+
+    // registered opcodes never clash with the built-in ones
+    fn instruction_length(&self, opcode: i32) -> Option<usize> {
+        match self.extensions.get(&opcode) {
+            Some(ext) => Some(ext.params.len() + 1),
+            None => instruction_length(opcode),
+        }
+    }
+
+    fn get_input(&mut self) -> Result<Option<W>, Error> {
         if let Some(input_type) = self.input.pop_front() {
             match input_type {
+                InputType::Values(mut values) => match values.pop_front() {
+                    Some(val) => {
+                        self.input.push_front(InputType::Values(values));
+                        Ok(Some(val))
+                    }
+                    None => self.get_input(),
+                },
+                #[cfg(feature = "std")]
                 InputType::StringCursor(mut c) => {
                     let mut input = String::new();
                     match c.read_line(&mut input) {
                         Ok(_) => {
-                            if input != "" {
+                            if !input.is_empty() {
                                 self.input.push_front(InputType::StringCursor(c));
-                                input
+                                self.parse_input(input).map(Some)
                             }
                             else {
                                 self.get_input()
@@ -211,117 +562,544 @@ impl Machine {
                         Err(_) => self.get_input(),
                     }
                 },
+                #[cfg(feature = "std")]
                 InputType::IntReceiver(rx) => {
-                    println!("tid {:?}: waiting on rx for input...", thread_id());
+                    trace!(self, "waiting on rx for input...");
                     match rx.recv() {
                         Ok(input) => {
                             self.input.push_front(InputType::IntReceiver(rx));
-                            input.to_string()
+                            Ok(Some(input))
                         }
                         Err(_) => self.get_input()
                     }
 
                 },
+                #[cfg(feature = "std")]
+                InputType::Stdin => {
+                    println!("tid {:?}: input a value: ", thread_id());
+                    let mut input = String::new();
+                    match io::stdin().read_line(&mut input) {
+                        Ok(n) if n > 0 => {
+                            self.input.push_front(InputType::Stdin);
+                            self.parse_input(input).map(Some)
+                        }
+                        _ => self.get_input(),
+                    }
+                },
             }
         } else {
-            println!("tid {:?}: input an i64 value: ", thread_id());
-            let mut input = String::new();
-            let stdin = std::io::stdin();
-            stdin.read_line(&mut input).unwrap();
-            input
+            match self.input_exhausted {
+                InputExhausted::Pause => Ok(None),
+                InputExhausted::Error => Err(Error::InputExhausted { pc: self.program_counter }),
+                #[cfg(feature = "std")]
+                InputExhausted::Block => {
+                    let (_, rx) = self.blocking_input.as_ref().unwrap();
+                    // the machine holds a sender itself so this never disconnects
+                    Ok(Some(rx.recv().unwrap()))
+                }
+            }
         }
     }
 
-    pub fn get_output(&self) -> &Vec<i64> {
+    pub fn get_output(&self) -> &Vec<W> {
         &self.output
     }
 
-    pub fn set_input(self: &mut Self, input: InputType) {
+    pub fn set_input(self: &mut Self, input: InputType<W>) {
         self.input.push_back(input);
     }
 
+    #[cfg(feature = "std")]
     pub fn set_input_string(self: &mut Self, input: String) {
         self.input.push_back(InputType::StringCursor(io::Cursor::new(input)));
     }
 
 
-    pub fn set_noun(self: &mut Self, noun: i64) {
-        self.memory[1] = noun;
+    pub fn set_noun(self: &mut Self, noun: W) -> Result<(), Error> {
+        self.poke(1, noun)
     }
 
-    pub fn set_verb(self: &mut Self, verb: i64) {
-        self.memory[2] = verb;
+    pub fn set_verb(self: &mut Self, verb: W) -> Result<(), Error> {
+        self.poke(2, verb)
     }
 
-    fn load(self: &Self, addr: i64) -> i64 {
-        let result = self.memory[addr as usize];
-        println!("tid {:?}: LOADING addr: {}, val: {}", thread_id(), addr, result);
-        result
+    /// Address of the next instruction to execute.
+    pub fn program_counter(&self) -> i64 {
+        self.program_counter
+    }
+
+    /// What relative-mode parameters are relative to.
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    /// Number of memory cells available to the program.
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// The contents of `addr`, or None if it is outside of memory.
+    pub fn peek(&self, addr: usize) -> Option<W> {
+        self.memory.get(addr).cloned()
+    }
+
+    /// Overwrite the contents of `addr`. Unlike writes made by the program itself this is never
+    /// reported as a self-modification, but it does set [`Machine::code_modified`] when it
+    /// changes previously executed memory.
+    pub fn poke(&mut self, addr: usize, val: W) -> Result<(), Error> {
+        self.write_range(addr, &[val])
+    }
+
+    /// The contents of `range`, or None if any of it is outside of memory.
+    pub fn read_range(&self, range: core::ops::Range<usize>) -> Option<&[W]> {
+        self.memory.get(range)
     }
 
-    fn load_with_mode(self: &Self, addr: i64, mode: i32) -> i64 {
+    /// Overwrite memory starting at `start` with `values`. Nothing is written if any of it would
+    /// land outside of memory.
+    pub fn write_range(&mut self, start: usize, values: &[W]) -> Result<(), Error> {
+        let end = start.checked_add(values.len()).filter(|end| *end <= self.memory.len());
+        let end = match end {
+            Some(end) => end,
+            None => {
+                // the first address that doesn't fit
+                let addr = start.max(self.memory.len()) as i64;
+                return Err(Error::InvalidAddress { pc: self.program_counter, addr });
+            }
+        };
+        for (addr, val) in (start..end).zip(values) {
+            if self.memory[addr] != *val && self.executed.contains_key(&addr) {
+                self.code_modified = true;
+            }
+            self.memory[addr] = val.clone();
+            self.invalidate_compiled(addr);
+        }
+        Ok(())
+    }
+
+    /// Every non-zero memory cell as `(address, value)`, in address order. Memory past the end
+    /// of the program starts out zeroed, so this is usually much shorter than the full memory.
+    pub fn memory_dump(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.memory.iter().cloned().enumerate().filter(|(_, val)| !val.is_zero())
+    }
+
+    // the index of `addr` in memory, if it's in memory at all
+    fn cell(&self, addr: i64) -> Result<usize, Error> {
+        usize::try_from(addr)
+            .ok()
+            .filter(|&index| index < self.memory.len())
+            .ok_or(Error::InvalidAddress { pc: self.program_counter, addr })
+    }
+
+    fn load(self: &Self, addr: i64) -> Result<W, Error> {
+        let result = self.memory[self.cell(addr)?].clone();
+        trace!(self, "LOADING addr: {}, val: {}", addr, result);
+        Ok(result)
+    }
+
+    // a cell used as an address rather than a value
+    fn load_address(&self, addr: i64) -> Result<i64, Error> {
+        Ok(self.load(addr)?.to_i64_saturating())
+    }
+
+    fn load_with_mode(self: &Self, addr: i64, mode: i32) -> Result<W, Error> {
         match mode {
-            0 => self.load(self.load(addr)),
+            0 => self.load(self.load_address(addr)?),
             1 => self.load(addr),
+            2 => self.load(self.relative_base.saturating_add(self.load_address(addr)?)),
             _ => unreachable!("invalid mode: {}", mode)
         }
     }
 
-    fn store(self: &mut Self, addr: i64, val: i64) {
-        self.memory[addr as usize] = val;
+    // where a parameter that is written to points; lenient modes treat immediate as position
+    fn write_address(&self, addr: i64, mode: i32) -> Result<i64, Error> {
+        match mode {
+            2 => Ok(self.relative_base.saturating_add(self.load_address(addr)?)),
+            _ => self.load_address(addr),
+        }
+    }
+
+    /// Record writes to memory that has already been executed as an instruction. See
+    /// [`Machine::self_modifications`] and [`Machine::code_modified`].
+    pub fn set_detect_self_modification(&mut self, enabled: bool) {
+        self.detect_self_modification = enabled;
     }
+
+    /// Every write to previously executed memory, in the order they happened.
+    pub fn self_modifications(&self) -> &[SelfModification<W>] {
+        &self.self_modifications
+    }
+
+    /// True once a write has changed previously executed memory. Anything caching decoded
+    /// instructions should throw its cache away and call [`Machine::clear_code_modified`].
+    pub fn code_modified(&self) -> bool {
+        self.code_modified
+    }
+
+    pub fn clear_code_modified(&mut self) {
+        self.code_modified = false;
+    }
+
+    fn store(self: &mut Self, addr: i64, val: W) -> Result<(), Error> {
+        let index = self.cell(addr)?;
+        if let Some(event) = &mut self.current_event {
+            event.writes.push((addr, val.clone()));
+        }
+        self.invalidate_compiled(index);
+        if self.detect_self_modification {
+            if let Some(&instruction) = self.executed.get(&index) {
+                let opcode_before = (self.memory[instruction].to_i64_saturating() % 100) as i32;
+                let before = core::mem::replace(&mut self.memory[index], val.clone());
+                self.code_modified |= before != val;
+                self.self_modifications.push(SelfModification {
+                    pc: self.program_counter,
+                    target: index,
+                    instruction,
+                    before,
+                    after: val,
+                    opcode_before,
+                    opcode_after: (self.memory[instruction].to_i64_saturating() % 100) as i32,
+                });
+                return Ok(());
+            }
+        }
+        self.memory[index] = val;
+        Ok(())
+    }
+}
+
+// ABCDE
+//  1002
+
+// DE - two-digit opcode,      02 == opcode 2
+// C - mode of 1st parameter,  0 == position mode
+// B - mode of 2nd parameter,  1 == immediate mode
+// A - mode of 3rd parameter,  0 == position mode,
+//                               omitted due to being a leading zero
+// Parameters that an instruction writes to will never be in immediate mode.
+// Returns None for words that can't be an instruction at all: negative or more than five digits.
+pub fn decode(instr: i64) -> Option<Instruction> {
+    if !(0..100000).contains(&instr) {
+        return None;
+    }
+    Some(Instruction {
+        opcode: (instr % 100) as i32,
+        mode_op1: (instr / 100 % 10) as i32,
+        mode_op2: (instr / 1000 % 10) as i32,
+        mode_op3: (instr / 10000 % 10) as i32,
+    })
 }
 
+/// How the parameters of each built-in instruction are used.
+pub(crate) fn builtin_params(opcode: i32) -> Option<&'static [Param]> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(&[Param::Read, Param::Read, Param::Write]),
+        3 => Some(&[Param::Write]),
+        4 | 9 => Some(&[Param::Read]),
+        5 | 6 => Some(&[Param::Read, Param::Read]),
+        99 => Some(&[]),
+        _ => None,
+    }
+}
+
+/// Number of memory cells taken up by an instruction with this opcode, including the opcode itself.
+pub(crate) fn instruction_length(opcode: i32) -> Option<usize> {
+    builtin_params(opcode).map(|params| params.len() + 1)
+}
+
+#[cfg(feature = "std")]
 fn thread_id() -> std::thread::ThreadId {
     std::thread::current().id()
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
     #[test]
     fn it_handles_test_cases() {
         let (_, mut m) = Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert!(m.memory == vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert!(m.memory.starts_with(&[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50]));
 
         let (_, mut m) = Machine::new(vec![1, 0, 0, 0, 99]);
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert!(m.memory == vec![2, 0, 0, 0, 99]);
+        assert!(m.memory.starts_with(&[2, 0, 0, 0, 99]));
 
         let (_, mut m) = Machine::new(vec![2, 3, 0, 3, 99]);
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert!(m.memory == vec![2, 3, 0, 6, 99]);
+        assert!(m.memory.starts_with(&[2, 3, 0, 6, 99]));
 
         let (_, mut m) = Machine::new(vec![2, 4, 4, 5, 99, 0]);
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert!(m.memory == vec![2, 4, 4, 5, 99, 9801]);
+        assert!(m.memory.starts_with(&[2, 4, 4, 5, 99, 9801]));
 
         let (_, mut m) = Machine::new(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert!(m.memory == vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
+        assert!(m.memory.starts_with(&[30, 1, 1, 4, 2, 5, 6, 0, 99]));
     }
 
     #[test]
     fn it_handles_input_instr_opcode_3() {
         let (_, mut m) = Machine::new(vec![3, 0, 99]);
         m.set_input_string("50".to_string());
-        m.execute();
+        m.execute().unwrap();
         println!("{:?}", m.memory);
-        assert_eq!(m.memory, vec![50, 0, 99]);
+        assert!(m.memory.starts_with(&[50, 0, 99]));
+    }
+
+    #[test]
+    fn it_detects_self_modifying_code() {
+        // ADD #1 #1 -> [0] overwrites its own opcode, the second ADD patches its own operand
+        let (_, mut m) = Machine::new(vec![1101, 1, 1, 0, 1101, 2, 2, 5, 99]);
+        m.set_detect_self_modification(true);
+        m.execute().unwrap();
+        assert_eq!(m.self_modifications(), &[
+            SelfModification { pc: 0, target: 0, instruction: 0, before: 1101, after: 2, opcode_before: 1, opcode_after: 2 },
+            SelfModification { pc: 4, target: 5, instruction: 4, before: 2, after: 4, opcode_before: 1, opcode_after: 1 },
+        ]);
+        assert!(m.code_modified());
+        m.clear_code_modified();
+        assert!(!m.code_modified());
+
+        // off by default
+        let (_, mut m) = Machine::new(vec![1101, 1, 1, 0, 99]);
+        m.execute().unwrap();
+        assert!(m.self_modifications().is_empty());
+        assert!(!m.code_modified());
+
+        // the day 2 example rewrites the operand of the instruction doing the write
+        let (_, mut m) = Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        m.set_detect_self_modification(true);
+        m.execute().unwrap();
+        assert_eq!(m.self_modifications(), &[
+            SelfModification { pc: 0, target: 3, instruction: 0, before: 3, after: 70, opcode_before: 1, opcode_after: 1 },
+            SelfModification { pc: 4, target: 0, instruction: 0, before: 1, after: 3500, opcode_before: 1, opcode_after: 0 },
+        ]);
+    }
+
+    #[test]
+    fn it_rejects_bad_parameter_modes() {
+        let (_, mut m) = Machine::new(vec![11101, 1, 1, 0, 99]);
+        assert_eq!(m.execute(), Err(Error::ImmediateWrite { pc: 0, param: 3 }));
+
+        let (_, mut m) = Machine::new(vec![103, 0, 99]);
+        m.set_input_string("5".to_string());
+        assert_eq!(m.execute(), Err(Error::ImmediateWrite { pc: 0, param: 1 }));
+
+        let (_, mut m) = Machine::new(vec![1101, 1, 1, 5, 304, 0, 99]);
+        assert_eq!(m.execute(), Err(Error::InvalidMode { pc: 4, param: 1, mode: 3 }));
+
+        let (_, mut m) = Machine::new(vec![1101, 1, 1, 5, 42, 99]);
+        assert_eq!(m.execute(), Err(Error::InvalidInstruction { pc: 4, instruction: 42 }));
+
+        let (_, mut m) = Machine::new(vec![-1, 99]);
+        assert_eq!(m.execute(), Err(Error::InvalidInstruction { pc: 0, instruction: -1 }));
+    }
+
+    #[test]
+    fn it_supports_relative_mode() {
+        // day 9 examples: a quine and large numbers
+        let quine = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let (_, mut m) = Machine::new(quine.clone());
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &quine);
+        assert_eq!(m.relative_base(), 16);
+
+        let (_, mut m) = Machine::new(vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![1219070632396864]);
+
+        // relative writes
+        let (_, mut m) = Machine::new(vec![109, 10, 203, 1, 21101, 2, 3, 2, 99]);
+        m.set_input_string("7\n".to_string());
+        m.execute().unwrap();
+        assert_eq!(m.peek(11), Some(7));
+        assert_eq!(m.peek(12), Some(5));
+    }
+
+    #[test]
+    fn it_ignores_write_modes_when_lenient() {
+        let (_, mut m) = Machine::new(vec![11101, 1, 1, 0, 99]);
+        m.set_lenient_modes(true);
+        m.execute().unwrap();
+        assert!(m.memory.starts_with(&[2, 1, 1, 0, 99]));
+
+        let (_, mut m) = Machine::new(vec![203, 0, 99]);
+        m.set_lenient_modes(true);
+        m.set_input_string("5".to_string());
+        m.execute().unwrap();
+        assert!(m.memory.starts_with(&[5, 0, 99]));
+
+        // read modes are still checked
+        let (_, mut m) = Machine::new(vec![304, 0, 99]);
+        m.set_lenient_modes(true);
+        assert_eq!(m.execute(), Err(Error::InvalidMode { pc: 0, param: 1, mode: 3 }));
+    }
+
+    #[test]
+    fn it_pauses_until_input_arrives() {
+        // out = in0 + in1
+        let (_, mut m) = Machine::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0]);
+        assert_eq!(m.execute(), Ok(Status::NeedsInput));
+        assert_eq!(m.execute(), Ok(Status::NeedsInput));
+        m.set_input_string("20".to_string());
+        assert_eq!(m.execute(), Ok(Status::NeedsInput));
+        assert_eq!(m.instructions_executed(), 1);
+        m.set_input_string("22".to_string());
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        assert_eq!(m.get_output(), &vec![42]);
+    }
+
+    #[test]
+    fn it_peeks_and_pokes_memory() {
+        let (_, mut m) = Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(m.peek(9), Some(30));
+        assert_eq!(m.peek(m.memory_size()), None);
+
+        m.write_range(9, &[3, 4, 5]).unwrap();
+        assert_eq!(m.read_range(9..12), Some(&[3, 4, 5][..]));
+
+        // patch the multiplication operand in between steps
+        assert_eq!(m.step(), Ok(None));
+        assert_eq!(m.peek(3), Some(7));
+        m.poke(11, 10).unwrap();
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        assert_eq!(m.peek(0), Some(70));
+
+        assert_eq!(
+            m.memory_dump().collect::<Vec<_>>(),
+            vec![(0, 70), (1, 9), (2, 10), (3, 7), (4, 2), (5, 3), (6, 11), (8, 99), (9, 3), (10, 4), (11, 10)]
+        );
+
+        let size = m.memory_size();
+        assert_eq!(m.read_range(size - 1..size + 1), None);
+        assert_eq!(m.write_range(size - 1, &[1, 2]), Err(Error::InvalidAddress { pc: 8, addr: size as i64 }));
+        assert_eq!(m.poke(size - 1, 1), Ok(()));
     }
 
     #[test]
     fn it_supports_large_numbers() {
         let (rx, mut m) = Machine::new(vec![104,1125899906842624,99]);
-        m.execute();
+        m.execute().unwrap();
 
         assert_eq!(rx.recv().unwrap(), 1125899906842624);
     }
+
+    #[test]
+    fn it_supports_wider_words() {
+        // (2^62)^2
+        let program = vec![1102, 1 << 62, 1 << 62, 7, 4, 7, 99, 0];
+        let (rx, mut m) = MachineBuilder::new().word::<i128>().trace(false).build(program);
+        m.execute().unwrap();
+        assert_eq!(rx.recv().unwrap(), 1 << 124);
+
+        #[cfg(feature = "bigint")]
+        {
+            use num_bigint::BigInt;
+            // (2^124)^2 doesn't fit in anything smaller
+            let program = vec![2, 7, 7, 7, 4, 7, 99, 0].into_iter().map(BigInt::from).collect::<Vec<_>>();
+            let (rx, mut m) = MachineBuilder::new().word::<BigInt>().trace(false).build(program);
+            m.poke(7, BigInt::from(1) << 124).unwrap();
+            m.execute().unwrap();
+            assert_eq!(rx.recv().unwrap(), BigInt::from(1) << 248);
+        }
+    }
+
+    #[test]
+    fn it_reports_overflow_when_checked() {
+        let program = vec![1101, 1, 1, 9, 1001, 9, i64::MAX, 10, 99, 0, 0];
+
+        let (_, mut m) = MachineBuilder::new().trace(false).build(program.clone());
+        m.execute().unwrap();
+        assert_eq!(m.peek(10), Some(i64::MIN + 1));
+
+        let (_, mut m) = MachineBuilder::new().trace(false).checked_arithmetic(true).build(program);
+        assert_eq!(m.execute(), Err(Error::Overflow { pc: 4 }));
+        assert_eq!(m.peek(10), Some(0));
+    }
+
+    #[test]
+    fn it_rejects_input_that_is_not_a_number() {
+        let (_, mut m) = Machine::new(vec![3, 0, 99]);
+        m.set_input_string("five\n".to_string());
+        assert_eq!(m.execute(), Err(Error::InvalidInput { pc: 0, input: "five\n".to_string() }));
+    }
+
+    #[test]
+    fn it_reports_addresses_outside_memory() {
+        let run = |program: Vec<i64>| {
+            MachineBuilder::new().trace(false).memory_multiplier(1).build_detached(program).execute()
+        };
+        assert_eq!(run(vec![4, -1, 99]), Err(Error::InvalidAddress { pc: 0, addr: -1 }));
+        assert_eq!(run(vec![1101, 1, 1, 4]), Err(Error::InvalidAddress { pc: 0, addr: 4 }));
+        assert_eq!(run(vec![109, -5, 204, 0, 99]), Err(Error::InvalidAddress { pc: 2, addr: -5 }));
+        // jumping out of memory, and an instruction cut off by the end of it
+        assert_eq!(run(vec![1105, 1, -3]), Err(Error::InvalidAddress { pc: -3, addr: -3 }));
+        assert_eq!(run(vec![1101, 1]), Err(Error::InvalidAddress { pc: 0, addr: 2 }));
+        assert_eq!(run(vec![]), Err(Error::InvalidAddress { pc: 0, addr: 0 }));
+
+        // the day 2 helpers on programs too short for them
+        let mut m = MachineBuilder::new().trace(false).memory_multiplier(1).build_detached(vec![99, 0]);
+        assert_eq!(m.set_noun(5), Ok(()));
+        assert_eq!(m.set_verb(5), Err(Error::InvalidAddress { pc: 0, addr: 2 }));
+        assert_eq!(m.output(), Ok(99));
+        let m = MachineBuilder::new().trace(false).build_detached(vec![]);
+        assert_eq!(m.output(), Err(Error::InvalidAddress { pc: 0, addr: 0 }));
+    }
+
+    // anything at all, but mostly words that decode to something
+    fn memory_images() -> impl proptest::strategy::Strategy<Value = Vec<i64>> {
+        use proptest::prelude::*;
+        let word = prop_oneof![
+            any::<i64>(),
+            -5..40i64,
+            (prop::sample::select(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 99]), 0..300i64)
+                .prop_map(|(opcode, modes)| modes * 100 + opcode),
+        ];
+        prop::collection::vec(word, 0..48)
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn it_never_panics_or_runs_forever(
+            memory in memory_images(),
+            values in proptest::collection::vec(proptest::prelude::any::<i64>(), 0..4),
+            text in "(-?[0-9]{1,3}\n){0,2}.{0,3}",
+        ) {
+            const BUDGET: u64 = 2000;
+            for setup in 0..5 {
+                for compiled in [false, true] {
+                    let mut m = MachineBuilder::new()
+                        .trace(false)
+                        .memory_multiplier(2)
+                        .instruction_budget(BUDGET)
+                        .checked_arithmetic(setup == 1)
+                        .record_trace(setup == 3)
+                        .input_exhausted(if setup == 4 { InputExhausted::Block } else { InputExhausted::Pause })
+                        .build_detached(memory.clone());
+                    m.set_lenient_modes(setup == 2);
+                    m.set_detect_self_modification(setup == 2);
+                    m.set_input(InputType::Values(values.iter().copied().collect()));
+                    m.set_input_string(text.clone());
+                    if let Some(tx) = m.input_sender() {
+                        // enough that it never waits
+                        for i in 0..=BUDGET as i64 {
+                            tx.send(i).unwrap();
+                        }
+                    }
+                    let _ = m.set_noun(1);
+                    let _ = m.set_verb(2);
+                    let _ = if compiled { m.execute_compiled() } else { m.execute() };
+                    let _ = m.output();
+                    proptest::prop_assert!(m.instructions_executed() <= BUDGET);
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,212 @@
+//! Linking separately assembled modules into one program.
+//!
+//! [`crate::asm::assemble_object`] turns a module into an [`Object`]: its code as if it were
+//! loaded at address 0, its symbols, and a relocation for every word that depends on where the
+//! module ends up or on a symbol from another module. [`link`] lays the objects out one after
+//! the other, so the first one is where execution starts, and patches those words. A library
+//! of routines can then be assembled once and linked into any program that uses it:
+//!
+//! ```text
+//! ; lib.s
+//!         GLOBAL double double.arg double.ret
+//! double: MULT [double.arg] #2 [double.arg]
+//!         JT #1 [double.ret]
+//! double.arg: DATA 0
+//! double.ret: DATA 0
+//!
+//! ; main.s
+//!         IN [double.arg]
+//!         ADD #back #0 [double.ret]
+//!         JT #1 #double
+//! back:   OUT [double.arg]
+//!         HALT
+//! ```
+//!
+//! With the `serde` feature objects are serializable, so the `json` module can store them.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::asm::Assembly;
+use crate::debug::DebugInfo;
+
+/// An assembled module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Object {
+    pub code: Vec<i64>,
+    pub symbols: BTreeMap<String, Symbol>,
+    pub relocations: Vec<Relocation>,
+    /// As if the module was loaded at address 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub debug: DebugInfo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Symbol {
+    pub value: i64,
+    /// Whether the value is an address in the module, and so moves with it.
+    pub relocatable: bool,
+    /// Whether other modules can refer to it.
+    pub global: bool,
+}
+
+/// Adds (or subtracts) the value of `target` to the word at `offset` in the code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relocation {
+    pub offset: usize,
+    pub target: Target,
+    pub negative: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Target {
+    /// The address the module is loaded at.
+    Base,
+    /// A global symbol from any module.
+    Symbol(String),
+}
+
+/// Modules are numbered by their position in the slice passed to [`link`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LinkError {
+    UndefinedSymbol { module: usize, name: String },
+    /// A global symbol defined by more than one module.
+    DuplicateSymbol { module: usize, name: String },
+    InvalidRelocation { module: usize, offset: usize },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::UndefinedSymbol { module, name } => write!(f, "module {}: undefined symbol {}", module, name),
+            LinkError::DuplicateSymbol { module, name } => {
+                write!(f, "module {}: {} is already defined by another module", module, name)
+            }
+            LinkError::InvalidRelocation { module, offset } => {
+                write!(f, "module {}: relocation at {} is outside the code", module, offset)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LinkError {}
+
+/// Links `objects` in order. The symbols of the result are the global ones, at their final
+/// addresses. Its debug info has every module's labels, local ones included; when modules share
+/// a label name the first module's is kept.
+pub fn link(objects: &[Object]) -> Result<Assembly, LinkError> {
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut len = 0;
+    for object in objects {
+        bases.push(len as i64);
+        len += object.code.len();
+    }
+
+    let mut globals = BTreeMap::new();
+    for (module, object) in objects.iter().enumerate() {
+        for (name, symbol) in object.symbols.iter().filter(|(_, symbol)| symbol.global) {
+            let value = if symbol.relocatable { symbol.value.wrapping_add(bases[module]) } else { symbol.value };
+            if globals.insert(name.clone(), value).is_some() {
+                return Err(LinkError::DuplicateSymbol { module, name: name.clone() });
+            }
+        }
+    }
+
+    let mut program = Vec::with_capacity(len);
+    let mut debug = DebugInfo { len: len as i64, ..DebugInfo::default() };
+    for (module, object) in objects.iter().enumerate() {
+        for (name, &addr) in &object.debug.labels {
+            debug.labels.entry(name.clone()).or_insert(addr + bases[module]);
+        }
+        debug.lines.extend(object.debug.lines.iter().map(|(&addr, line)| (addr + bases[module], line.clone())));
+        let mut code = object.code.clone();
+        for relocation in &object.relocations {
+            let value = match &relocation.target {
+                Target::Base => bases[module],
+                Target::Symbol(name) => *globals
+                    .get(name)
+                    .ok_or_else(|| LinkError::UndefinedSymbol { module, name: name.to_string() })?,
+            };
+            let word = code
+                .get_mut(relocation.offset)
+                .ok_or(LinkError::InvalidRelocation { module, offset: relocation.offset })?;
+            *word = if relocation.negative { word.wrapping_sub(value) } else { word.wrapping_add(value) };
+        }
+        program.extend(code);
+    }
+    Ok(Assembly { program, symbols: globals, debug })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, assemble_object};
+    use crate::Machine;
+
+    const LIB: &str = "
+        GLOBAL double double.arg double.ret
+double: MULT [double.arg] #2 [double.arg]
+        JT #1 [double.ret]
+double.arg: DATA 0
+double.ret: DATA 0
+";
+
+    const MAIN: &str = "
+        IN [double.arg]
+        ADD #back #0 [double.ret]
+        JT #1 #double
+back:   OUT [double.arg]
+        JT #1 #loop
+loop:   HALT                ; a local label, like the library's own
+size = back-loop            ; doesn't move with the module
+";
+
+    #[test]
+    fn it_links_modules() {
+        let lib = assemble_object(&(LIB.to_string() + "loop: HALT\n")).unwrap();
+        let main = assemble_object(MAIN).unwrap();
+        assert_eq!(main.symbols["size"], Symbol { value: -5, relocatable: false, global: false });
+        assert_eq!(
+            main.relocations[..2],
+            [
+                Relocation { offset: 1, target: Target::Symbol("double.arg".to_string()), negative: false },
+                Relocation { offset: 3, target: Target::Base, negative: false },
+            ]
+        );
+
+        let linked = link(&[main, lib]).unwrap();
+        assert_eq!(linked.symbols.keys().collect::<Vec<_>>(), ["double", "double.arg", "double.ret"]);
+        assert_eq!(linked.symbols["double"], 15);
+        assert_eq!(linked.debug.describe(16), "16 (double+1), line 3: double: MULT [double.arg] #2 [double.arg]");
+        assert_eq!(linked.debug.labels["loop"], 14);
+        // the same as assembling everything together, bar the extra HALT
+        let whole = assemble(&(MAIN.to_string() + LIB)).unwrap();
+        assert_eq!(linked.program[..whole.program.len()], whole.program[..]);
+
+        let (_, mut m) = Machine::new(linked.program);
+        m.set_input_string("21\n".to_string());
+        m.execute().unwrap();
+        assert_eq!(m.get_output(), &vec![42]);
+    }
+
+    #[test]
+    fn it_reports_unresolved_and_duplicate_symbols() {
+        let lib = assemble_object(LIB).unwrap();
+        let main = assemble_object(MAIN).unwrap();
+        assert_eq!(
+            link(core::slice::from_ref(&main)).unwrap_err(),
+            LinkError::UndefinedSymbol { module: 0, name: "double.arg".to_string() }
+        );
+        assert_eq!(
+            link(&[main, lib.clone(), lib]).unwrap_err(),
+            LinkError::DuplicateSymbol { module: 2, name: "double".to_string() }
+        );
+    }
+}
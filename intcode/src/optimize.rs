@@ -0,0 +1,413 @@
+//! Peephole optimization of intcode programs.
+//!
+//! [`optimize`] rewrites instructions in place, so nothing moves and every address stays valid:
+//!
+//! - operands whose value is known turn into immediate-mode operands,
+//! - arithmetic and comparisons on known values fold into a single constant store
+//!   (`1101, value, 0, out`), including the identities `x * 0`, `x == x` and `x < x`,
+//! - conditional jumps that are always taken become unconditional ones,
+//! - jumps to an unconditional jump go straight to its target.
+//!
+//! A value is known if it's an immediate, a cell no reachable instruction ever writes, or a cell
+//! written earlier in the same basic block with a known value. All of that relies on the control
+//! flow graph from [`crate::cfg`] covering the whole program, so programs it can't fully resolve
+//! (computed jumps, invalid instructions), that use relative-mode operands, that write to their
+//! own instructions or that run instructions overlapping each other are left alone.
+//! Instructions whose cells the program reads as data aren't rewritten either.
+//!
+//! The optimized program produces the same output from the same input, in fewer or cheaper steps.
+//! Only the rewritten instruction cells differ in memory.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+use crate::cfg::{Cfg, Exit};
+use crate::extension::Param;
+use crate::{builtin_params, decode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RewriteKind {
+    /// Operands with a known value became immediates.
+    ConstantOperands,
+    /// The result is known, the instruction stores it directly.
+    ConstantFolded,
+    /// A conditional jump that is always taken became unconditional.
+    BranchResolved,
+    /// A jump to an unconditional jump now goes to its target.
+    JumpThreaded,
+}
+
+impl fmt::Display for RewriteKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RewriteKind::ConstantOperands => write!(f, "constant operands"),
+            RewriteKind::ConstantFolded => write!(f, "constant folded"),
+            RewriteKind::BranchResolved => write!(f, "branch resolved"),
+            RewriteKind::JumpThreaded => write!(f, "jump threaded"),
+        }
+    }
+}
+
+/// One rewritten instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    pub addr: usize,
+    pub kind: RewriteKind,
+    pub before: Vec<i64>,
+    pub after: Vec<i64>,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:?} -> {:?} ({})", self.addr, self.before, self.after, self.kind)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Optimized {
+    pub program: Vec<i64>,
+    /// Every rewrite in the order it was made. An instruction can be rewritten more than once.
+    pub rewrites: Vec<Rewrite>,
+}
+
+impl Optimized {
+    /// The rewrites, one per line.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for rewrite in &self.rewrites {
+            writeln!(report, "{}", rewrite).unwrap();
+        }
+        report
+    }
+}
+
+/// Why a program can't be optimized safely.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptimizeError {
+    /// The jump at `pc` goes to an address read from memory.
+    IndirectJump { pc: usize },
+    /// Execution can reach something at `pc` that isn't a built-in instruction.
+    InvalidInstruction { pc: usize },
+    /// The instruction at `pc` uses a negative address.
+    InvalidAddress { pc: usize, addr: i64 },
+    /// The instruction at `pc` has a relative-mode operand, which could point anywhere.
+    RelativeAddress { pc: usize },
+    /// The instruction at `pc` writes to the instruction at `target`.
+    SelfModifying { pc: usize, target: usize },
+    /// The instruction at `pc` shares cells with the instruction at `other`, and both run.
+    OverlappingInstructions { pc: usize, other: usize },
+}
+
+impl fmt::Display for OptimizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptimizeError::IndirectJump { pc } => write!(f, "indirect jump at pc {}", pc),
+            OptimizeError::InvalidInstruction { pc } => write!(f, "invalid instruction at pc {}", pc),
+            OptimizeError::InvalidAddress { pc, addr } => write!(f, "invalid address {} at pc {}", addr, pc),
+            OptimizeError::RelativeAddress { pc } => write!(f, "relative-mode operand at pc {}", pc),
+            OptimizeError::SelfModifying { pc, target } => {
+                write!(f, "instruction at pc {} writes to the instruction at {}", pc, target)
+            }
+            OptimizeError::OverlappingInstructions { pc, other } => {
+                write!(f, "instruction at pc {} overlaps the instruction at {}", pc, other)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OptimizeError {}
+
+/// Optimize `program` as it will be loaded, i.e. with any patching (like day 2's noun and verb)
+/// already applied.
+pub fn optimize(program: &[i64]) -> Result<Optimized, OptimizeError> {
+    let mut optimized = Optimized { program: program.to_vec(), rewrites: vec![] };
+    // Resolved branches can make code unreachable, which can make more cells constant, so
+    // repeat until nothing changes.
+    loop {
+        let analysis = Analysis::new(&optimized.program)?;
+        let before = optimized.rewrites.len();
+        for block in analysis.cfg.blocks.values() {
+            fold_block(&analysis, &block.instructions, &mut optimized);
+        }
+        for block in analysis.cfg.blocks.values() {
+            thread_jump(&analysis, *block.instructions.last().unwrap(), &mut optimized);
+        }
+        if optimized.rewrites.len() == before {
+            return Ok(optimized);
+        }
+    }
+}
+
+// A built-in instruction with its operands.
+struct Decoded {
+    opcode: i32,
+    modes: [i32; 3],
+    params: &'static [Param],
+    args: Vec<i64>,
+}
+
+impl Decoded {
+    // None if `addr` doesn't hold a built-in instruction
+    fn at(program: &[i64], addr: usize) -> Option<Decoded> {
+        let instr = decode(*program.get(addr)?)?;
+        let params = builtin_params(instr.opcode)?;
+        let args = program.get(addr + 1..addr + 1 + params.len())?.to_vec();
+        Some(Decoded { opcode: instr.opcode, modes: [instr.mode_op1, instr.mode_op2, instr.mode_op3], params, args })
+    }
+
+    fn len(&self) -> usize {
+        self.args.len() + 1
+    }
+
+    fn words(&self) -> Vec<i64> {
+        let word = self.modes.iter().rev().fold(0, |word, mode| word * 10 + *mode as i64) * 100 + self.opcode as i64;
+        let mut words = vec![word];
+        words.extend(&self.args);
+        words
+    }
+}
+
+struct Analysis {
+    cfg: Cfg,
+    /// Cells some reachable instruction writes to.
+    written: BTreeSet<usize>,
+    /// Cells some reachable instruction reads in position mode.
+    read: BTreeSet<usize>,
+}
+
+impl Analysis {
+    fn new(program: &[i64]) -> Result<Analysis, OptimizeError> {
+        let cfg = Cfg::build(program);
+        let mut analysis = Analysis { cfg, written: BTreeSet::new(), read: BTreeSet::new() };
+        // which instruction every cell of code belongs to
+        let mut code = BTreeMap::new();
+        for block in analysis.cfg.blocks.values() {
+            let last = *block.instructions.last().unwrap();
+            match block.exit {
+                Exit::IndirectJump => return Err(OptimizeError::IndirectJump { pc: last }),
+                Exit::Invalid => return Err(OptimizeError::InvalidInstruction { pc: last }),
+                _ => {}
+            }
+            for &pc in &block.instructions {
+                let instr = Decoded::at(program, pc).ok_or(OptimizeError::InvalidInstruction { pc })?;
+                for cell in pc..pc + instr.len() {
+                    if let Some(other) = code.insert(cell, pc) {
+                        return Err(OptimizeError::OverlappingInstructions { pc, other });
+                    }
+                }
+                for (i, param) in instr.params.iter().enumerate() {
+                    match instr.modes[i] {
+                        0 => {}
+                        2 => return Err(OptimizeError::RelativeAddress { pc }),
+                        _ => continue,
+                    }
+                    let addr = instr.args[i];
+                    if addr < 0 {
+                        return Err(OptimizeError::InvalidAddress { pc, addr });
+                    }
+                    match param {
+                        Param::Read => analysis.read.insert(addr as usize),
+                        Param::Write => analysis.written.insert(addr as usize),
+                    };
+                }
+            }
+        }
+        for block in analysis.cfg.blocks.values() {
+            for &pc in &block.instructions {
+                let instr = Decoded::at(program, pc).unwrap();
+                for (i, param) in instr.params.iter().enumerate() {
+                    if *param == Param::Write {
+                        if let Some(&target) = code.get(&(instr.args[i] as usize)) {
+                            return Err(OptimizeError::SelfModifying { pc, target });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(analysis)
+    }
+
+    // the cells of an instruction can only change if nothing reads them as data
+    fn rewritable(&self, addr: usize, len: usize) -> bool {
+        self.read.range(addr..addr + len).next().is_none()
+    }
+}
+
+fn rewrite(analysis: &Analysis, optimized: &mut Optimized, addr: usize, kind: RewriteKind, after: Vec<i64>) {
+    let before = optimized.program[addr..addr + after.len()].to_vec();
+    if before == after || !analysis.rewritable(addr, after.len()) {
+        return;
+    }
+    optimized.program[addr..addr + after.len()].copy_from_slice(&after);
+    optimized.rewrites.push(Rewrite { addr, kind, before, after });
+}
+
+fn fold_block(analysis: &Analysis, instructions: &[usize], optimized: &mut Optimized) {
+    // cells written earlier in the block with a known value, None once the value is unknown
+    let mut known: BTreeMap<usize, Option<i64>> = BTreeMap::new();
+    for &pc in instructions {
+        // analysis rules out instructions overlapping, so rewrites never break another one
+        let mut instr = match Decoded::at(&optimized.program, pc) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        let program = &optimized.program;
+        let value = |i: usize| match instr.modes[i] {
+            1 => Some(instr.args[i]),
+            _ => {
+                let addr = instr.args[i] as usize;
+                match known.get(&addr) {
+                    Some(val) => *val,
+                    None if !analysis.written.contains(&addr) => program.get(addr).cloned(),
+                    None => None,
+                }
+            }
+        };
+        let reads = (0..instr.params.len()).map(value).collect::<Vec<_>>();
+        let same_cell = instr.params.len() > 1 && instr.modes[0] == 0 && instr.modes[1] == 0 && instr.args[0] == instr.args[1];
+        let (a, b) = (reads.first().cloned().flatten(), reads.get(1).cloned().flatten());
+        let result = match (instr.opcode, a, b) {
+            (1, Some(a), Some(b)) => a.checked_add(b),
+            (2, Some(a), Some(b)) => a.checked_mul(b),
+            (2, Some(0), _) | (2, _, Some(0)) => Some(0),
+            (7, Some(a), Some(b)) => Some((a < b) as i64),
+            (8, Some(a), Some(b)) => Some((a == b) as i64),
+            (7, _, _) if same_cell => Some(0),
+            (8, _, _) if same_cell => Some(1),
+            _ => None,
+        };
+
+        let mut kind = RewriteKind::ConstantOperands;
+        for (i, read) in reads.iter().enumerate() {
+            if let (Param::Read, Some(val)) = (instr.params[i], read) {
+                instr.modes[i] = 1;
+                instr.args[i] = *val;
+            }
+        }
+        match instr.opcode {
+            1 | 2 | 7 | 8 if instr.modes[2] == 0 => {
+                let out = instr.args[2];
+                if let Some(val) = result {
+                    instr = Decoded { opcode: 1, modes: [1, 1, 0], params: instr.params, args: vec![val, 0, out] };
+                    kind = RewriteKind::ConstantFolded;
+                }
+                known.insert(out as usize, result);
+            }
+            3 => {
+                known.insert(instr.args[0] as usize, None);
+            }
+            5 | 6 => {
+                if let (Some(cond), Some(target)) = (reads[0], reads[1]) {
+                    if (cond != 0) == (instr.opcode == 5) && target >= 0 {
+                        instr = Decoded { opcode: 5, modes: [1, 1, 0], params: instr.params, args: vec![1, target] };
+                        kind = RewriteKind::BranchResolved;
+                    }
+                }
+            }
+            _ => {}
+        }
+        rewrite(analysis, optimized, pc, kind, instr.words());
+    }
+}
+
+// where an unconditional jump at `addr` goes, if it's one
+fn jump_target(program: &[i64], addr: usize) -> Option<i64> {
+    let instr = Decoded::at(program, addr)?;
+    let always = match instr.opcode {
+        5 => instr.args[0] != 0,
+        6 => instr.args[0] == 0,
+        _ => false,
+    };
+    if always && instr.modes[0] == 1 && instr.modes[1] == 1 && instr.args[1] >= 0 {
+        Some(instr.args[1])
+    } else {
+        None
+    }
+}
+
+fn thread_jump(analysis: &Analysis, pc: usize, optimized: &mut Optimized) {
+    let mut instr = match Decoded::at(&optimized.program, pc) {
+        Some(instr) if (instr.opcode == 5 || instr.opcode == 6) && instr.modes[1] == 1 => instr,
+        _ => return,
+    };
+    let mut target = instr.args[1];
+    let mut seen = BTreeSet::new();
+    seen.insert(pc);
+    while seen.insert(target as usize) {
+        match jump_target(&optimized.program, target as usize) {
+            Some(next) => target = next,
+            None => break,
+        }
+    }
+    instr.args[1] = target;
+    rewrite(analysis, optimized, pc, RewriteKind::JumpThreaded, instr.words());
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{MachineBuilder, Status};
+
+    fn run(program: &[i64], input: &str) -> (Vec<i64>, u64) {
+        let (_, mut m) = MachineBuilder::new().trace(false).instruction_budget(10_000).build(program.to_vec());
+        m.set_input_string(input.to_string());
+        assert_eq!(m.execute(), Ok(Status::Halted));
+        (m.get_output().clone(), m.instructions_executed())
+    }
+
+    #[test]
+    fn it_folds_constants_and_threads_jumps() {
+        #[rustfmt::skip]
+        let program = [
+            1, 30, 31, 32,   // 0: c = two + three
+            1002, 32, 1, 32, // 4: c = c * 1
+            3, 33,           // 8: x = in
+            8, 32, 34, 35,   // 10: t = c == five
+            1005, 35, 20,    // 14: if t goto 20
+            4, 33,           // 17: out x
+            99,              // 19
+            1105, 1, 23,     // 20: goto 23
+            4, 32,           // 23: out c
+            99,              // 25
+            0, 0, 0, 0, 2, 3, 0, 0, 5, 0,
+        ];
+        let optimized = optimize(&program).unwrap();
+        assert_eq!(&optimized.program[..17], &[1101, 5, 0, 32, 1101, 5, 0, 32, 3, 33, 1101, 1, 0, 35, 1105, 1, 23]);
+        assert_eq!(&optimized.program[17..], &program[17..]);
+        assert_eq!(optimized.report(), "\
+0: [1, 30, 31, 32] -> [1101, 5, 0, 32] (constant folded)
+4: [1002, 32, 1, 32] -> [1101, 5, 0, 32] (constant folded)
+10: [8, 32, 34, 35] -> [1101, 1, 0, 35] (constant folded)
+14: [1005, 35, 20] -> [1105, 1, 20] (branch resolved)
+14: [1105, 1, 20] -> [1105, 1, 23] (jump threaded)
+");
+        assert_eq!(run(&program, "7\n"), (vec![5], 8));
+        assert_eq!(run(&optimized.program, "7\n"), (vec![5], 7));
+    }
+
+    #[test]
+    fn it_leaves_code_read_as_data_alone() {
+        // the ADD reads its own opcode
+        let program = [1, 0, 7, 8, 4, 8, 99, 5, 0];
+        let optimized = optimize(&program).unwrap();
+        assert_eq!(optimized.program, vec![1, 0, 7, 8, 104, 6, 99, 5, 0]);
+        assert_eq!(optimized.rewrites.len(), 1);
+        assert_eq!(optimized.rewrites[0].kind, RewriteKind::ConstantOperands);
+        assert_eq!(run(&optimized.program, "").0, run(&program, "").0);
+    }
+
+    #[test]
+    fn it_refuses_programs_it_cannot_analyze() {
+        assert_eq!(optimize(&[1101, 1, 1, 5, 1104, 0, 99]), Err(OptimizeError::SelfModifying { pc: 0, target: 4 }));
+        assert_eq!(optimize(&[105, 1, 4, 99, 3]), Err(OptimizeError::IndirectJump { pc: 0 }));
+        assert_eq!(optimize(&[1, 0, 0, 0, 42]), Err(OptimizeError::InvalidInstruction { pc: 4 }));
+        // the jump lands in the operands of the MULT, which folding would have overwritten
+        let program = [102, 3, 4, 7, 1105, 5, 2, 4, 7, 99, 0];
+        assert_eq!(optimize(&program), Err(OptimizeError::OverlappingInstructions { pc: 2, other: 0 }));
+    }
+}
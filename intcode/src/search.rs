@@ -0,0 +1,203 @@
+//! Parallel search over program inputs.
+//!
+//! Puzzles like day 2 ask "which inputs make the program produce X?". Rather than looping over
+//! candidates one machine at a time, hand the program, a search space and a predicate to
+//! [`find_first`] or [`find_all`] and the candidates are run across a pool of threads.
+//! Candidates that make the program fail with an [`crate::Error`] never match, and neither do
+//! patches outside memory or candidates still running after [`CANDIDATE_BUDGET`] instructions.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::{InputExhausted, Machine, MachineBuilder};
+
+/// Instructions a candidate may run before it's given up on, so a loop can't hang the search.
+pub const CANDIDATE_BUDGET: u64 = 1_000_000;
+
+/// A single point in the search space: how the program is prepared before it is run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Candidate {
+    /// Overwrite memory cells `(address, value)` before execution.
+    Patch(Vec<(usize, i64)>),
+    /// Feed these values to the program's input instructions, in order.
+    Inputs(Vec<i64>),
+}
+
+/// A candidate that satisfied the predicate along with the machine it finished in.
+#[derive(Debug)]
+pub struct Match {
+    /// Position of the candidate in the search space.
+    pub index: usize,
+    pub candidate: Candidate,
+    pub machine: Machine,
+}
+
+/// The day 2 search space: every `(noun, verb)` pair patched into addresses 1 and 2.
+pub fn noun_verb(
+    nouns: std::ops::Range<i64>,
+    verbs: std::ops::Range<i64>,
+) -> impl Iterator<Item = Candidate> {
+    nouns.flat_map(move |noun| {
+        verbs
+            .clone()
+            .map(move |verb| Candidate::Patch(vec![(1, noun), (2, verb)]))
+    })
+}
+
+/// Number of worker threads to use when the caller has no preference.
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Run candidates until one satisfies `predicate`, returning the earliest matching candidate in
+/// search order.
+///
+/// Workers stop picking up new candidates once a match is known, so later candidates are not
+/// run needlessly. Candidates ahead of the current best match are still finished so the result
+/// does not depend on thread scheduling.
+pub fn find_first<I, P>(program: &[i64], candidates: I, threads: usize, predicate: P) -> Option<Match>
+where
+    I: IntoIterator<Item = Candidate>,
+    I::IntoIter: Send,
+    P: Fn(&Machine) -> bool + Sync,
+{
+    let mut matches = search(program, candidates, threads, &predicate, true);
+    matches.sort_by_key(|m| m.index);
+    matches.into_iter().next()
+}
+
+/// Run every candidate and return all that satisfy `predicate`, in search order.
+pub fn find_all<I, P>(program: &[i64], candidates: I, threads: usize, predicate: P) -> Vec<Match>
+where
+    I: IntoIterator<Item = Candidate>,
+    I::IntoIter: Send,
+    P: Fn(&Machine) -> bool + Sync,
+{
+    let mut matches = search(program, candidates, threads, &predicate, false);
+    matches.sort_by_key(|m| m.index);
+    matches
+}
+
+fn search<I, P>(
+    program: &[i64],
+    candidates: I,
+    threads: usize,
+    predicate: &P,
+    stop_early: bool,
+) -> Vec<Match>
+where
+    I: IntoIterator<Item = Candidate>,
+    I::IntoIter: Send,
+    P: Fn(&Machine) -> bool + Sync,
+{
+    let queue = Mutex::new(candidates.into_iter().enumerate());
+    let best = AtomicUsize::new(usize::MAX);
+    let found = Mutex::new(vec![]);
+
+    thread::scope(|s| {
+        for _ in 0..threads.max(1) {
+            s.spawn(|| loop {
+                let next = queue.lock().unwrap().next();
+                let (index, candidate) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                if stop_early && index > best.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                if let Some(machine) = run(program, &candidate) {
+                    if predicate(&machine) {
+                        best.fetch_min(index, Ordering::SeqCst);
+                        found.lock().unwrap().push(Match { index, candidate, machine });
+                    }
+                }
+            });
+        }
+    });
+
+    found.into_inner().unwrap()
+}
+
+// None if the program failed, such candidates never match
+fn run(program: &[i64], candidate: &Candidate) -> Option<Machine> {
+    // nobody listens to the output channel; sends to it are dropped once rx goes away
+    let (_, mut machine) = MachineBuilder::new()
+        .trace(false)
+        .input_exhausted(InputExhausted::Error)
+        .instruction_budget(CANDIDATE_BUDGET)
+        .build(program.to_vec());
+    match candidate {
+        Candidate::Patch(patches) => {
+            for &(addr, val) in patches {
+                machine.poke(addr, val).ok()?;
+            }
+        }
+        Candidate::Inputs(inputs) => {
+            let lines = inputs.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+            machine.set_input_string(lines.join("\n"));
+        }
+    }
+    machine.execute().ok()?;
+    Some(machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // output = noun * verb, stored at address 0
+    const MULTIPLY: [i64; 5] = [1102, 0, 0, 0, 99];
+
+    #[test]
+    fn it_finds_the_earliest_patch() {
+        let found = find_first(&MULTIPLY, noun_verb(0..10, 0..10), 4, |m| m.output() == Ok(12)).unwrap();
+        assert_eq!(found.candidate, Candidate::Patch(vec![(1, 2), (2, 6)]));
+        assert_eq!(found.machine.output(), Ok(12));
+    }
+
+    #[test]
+    fn it_finds_all_matches_in_order() {
+        let found = find_all(&MULTIPLY, noun_verb(0..10, 0..10), 3, |m| m.output() == Ok(12));
+        let pairs = found
+            .iter()
+            .map(|m| m.candidate.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                Candidate::Patch(vec![(1, 2), (2, 6)]),
+                Candidate::Patch(vec![(1, 3), (2, 4)]),
+                Candidate::Patch(vec![(1, 4), (2, 3)]),
+                Candidate::Patch(vec![(1, 6), (2, 2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_searches_input_vectors() {
+        // out = in0 + in1
+        let program = [3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+        let candidates = (0..5).map(|a| Candidate::Inputs(vec![a, 10]));
+        let found = find_first(&program, candidates, 2, |m| m.get_output() == &vec![13]).unwrap();
+        assert_eq!(found.candidate, Candidate::Inputs(vec![3, 10]));
+
+        assert!(find_first(&program, vec![Candidate::Inputs(vec![0, 0])], 2, |m| m.output() == Ok(99))
+            .is_none());
+    }
+
+    #[test]
+    fn it_skips_bad_patches_and_endless_loops() {
+        // jumps back to itself unless patched
+        let program = [1105, 1, 0, 99];
+        let candidates = vec![
+            Candidate::Patch(vec![(usize::MAX, 3)]),
+            Candidate::Patch(vec![(2, 0)]),
+            Candidate::Patch(vec![(2, 3)]),
+        ];
+        let found = find_all(&program, candidates, 2, |_| true);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].candidate, Candidate::Patch(vec![(2, 3)]));
+    }
+}
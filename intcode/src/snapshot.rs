@@ -0,0 +1,73 @@
+use alloc::vec::Vec;
+
+use crate::{Machine, Word};
+
+/// The state of a machine at some point of its execution: memory, program counter and output.
+/// Input sources, output channels and registered opcodes aren't part of a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot<W = i64> {
+    /// Memory up to the last non-zero cell.
+    pub memory: Vec<W>,
+    /// Number of memory cells, including the zeroed ones left out of `memory`.
+    pub memory_size: usize,
+    pub program_counter: i64,
+    /// Missing from snapshots saved before relative mode existed.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub relative_base: i64,
+    pub output: Vec<W>,
+    pub instructions_executed: u64,
+}
+
+impl<W: Word> Machine<W> {
+    pub fn snapshot(&self) -> Snapshot<W> {
+        let used = self.memory.iter().rposition(|val| !val.is_zero()).map_or(0, |last| last + 1);
+        Snapshot {
+            memory: self.memory[..used].to_vec(),
+            memory_size: self.memory.len(),
+            program_counter: self.program_counter,
+            relative_base: self.relative_base,
+            output: self.output.clone(),
+            instructions_executed: self.instructions_executed,
+        }
+    }
+
+    /// Put the machine back into the state of `snapshot`. Everything not covered by the snapshot
+    /// is left alone.
+    pub fn restore(&mut self, snapshot: &Snapshot<W>) {
+        self.memory = snapshot.memory.clone();
+        self.memory.resize(snapshot.memory_size.max(snapshot.memory.len()), W::from_i64(0));
+        self.program_counter = snapshot.program_counter;
+        self.relative_base = snapshot.relative_base;
+        self.output = snapshot.output.clone();
+        self.instructions_executed = snapshot.instructions_executed;
+        self.executed.clear();
+        self.compiled.clear();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use crate::{Machine, Status};
+
+    #[test]
+    fn it_resumes_from_a_snapshot() {
+        // out = in0 + in1
+        let program = vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+        let (_, mut m) = Machine::new(program.clone());
+        m.set_input_string("20\n".to_string());
+        assert_eq!(m.execute(), Ok(Status::NeedsInput));
+        let snapshot = m.snapshot();
+        assert_eq!(snapshot.memory, vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 20]);
+        assert_eq!(snapshot.memory_size, m.memory_size());
+        assert_eq!(snapshot.program_counter, 2);
+
+        let (_, mut resumed) = Machine::new(vec![]);
+        resumed.restore(&snapshot);
+        assert_eq!(resumed.memory_size(), m.memory_size());
+        resumed.set_input_string("22\n".to_string());
+        assert_eq!(resumed.execute(), Ok(Status::Halted));
+        assert_eq!(resumed.get_output(), &vec![42]);
+        assert_eq!(resumed.instructions_executed(), 5);
+    }
+}
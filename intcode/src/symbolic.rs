@@ -0,0 +1,490 @@
+//! Symbolic execution of intcode programs.
+//!
+//! Instead of trying every noun/verb pair, mark the interesting memory cells (or the program's
+//! inputs) as symbols and run the program once. Arithmetic builds up [`Expr`] trees, and when
+//! the result is linear in the symbols [`Linear::solve`] answers "which values produce X?"
+//! directly.
+//!
+//! Only data can be symbolic: instruction words, write addresses, jump conditions and relative
+//! base adjustments have to stay concrete, otherwise execution stops with a [`SymbolicError`].
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use crate::{decode, MachineBuilder};
+
+/// An unknown value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Symbol {
+    /// The initial contents of a memory cell.
+    Memory(usize),
+    /// The n-th value read by an input instruction.
+    Input(usize),
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Memory(addr) => write!(f, "mem[{}]", addr),
+            Symbol::Input(n) => write!(f, "in{}", n),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Const(i64),
+    Sym(Symbol),
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    LessThan(Box<Expr>, Box<Expr>),
+    Equals(Box<Expr>, Box<Expr>),
+    /// A read from an address that depends on a symbol. Nothing is known about the value.
+    Load(Box<Expr>),
+}
+
+impl Expr {
+    pub fn as_const(&self) -> Option<i64> {
+        match self {
+            Expr::Const(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn add(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_add(b)),
+            (Expr::Const(0), e) | (e, Expr::Const(0)) => e,
+            (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn mul(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.wrapping_mul(b)),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), e) | (e, Expr::Const(1)) => e,
+            (a, b) => Expr::Mul(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn less_than(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as i64),
+            (a, b) => Expr::LessThan(Box::new(a), Box::new(b)),
+        }
+    }
+
+    fn equals(a: Expr, b: Expr) -> Expr {
+        match (a, b) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as i64),
+            (a, b) => Expr::Equals(Box::new(a), Box::new(b)),
+        }
+    }
+
+    /// Flatten the expression into `constant + sum(coefficient * symbol)`, if it is linear.
+    pub fn linear(&self) -> Option<Linear> {
+        match self {
+            Expr::Const(c) => Some(Linear { constant: *c, terms: BTreeMap::new() }),
+            Expr::Sym(s) => {
+                let mut terms = BTreeMap::new();
+                terms.insert(*s, 1);
+                Some(Linear { constant: 0, terms })
+            }
+            Expr::Add(a, b) => {
+                let mut a = a.linear()?;
+                let b = b.linear()?;
+                a.constant = a.constant.checked_add(b.constant)?;
+                for (sym, coef) in b.terms {
+                    let entry = a.terms.entry(sym).or_insert(0);
+                    *entry = entry.checked_add(coef)?;
+                }
+                a.terms.retain(|_, coef| *coef != 0);
+                Some(a)
+            }
+            Expr::Mul(a, b) => {
+                let (a, b) = (a.linear()?, b.linear()?);
+                let (scale, mut l) = if a.terms.is_empty() {
+                    (a.constant, b)
+                } else if b.terms.is_empty() {
+                    (b.constant, a)
+                } else {
+                    return None;
+                };
+                l.constant = l.constant.checked_mul(scale)?;
+                for coef in l.terms.values_mut() {
+                    *coef = coef.checked_mul(scale)?;
+                }
+                l.terms.retain(|_, coef| *coef != 0);
+                Some(l)
+            }
+            Expr::LessThan(..) | Expr::Equals(..) | Expr::Load(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(c) => write!(f, "{}", c),
+            Expr::Sym(s) => write!(f, "{}", s),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equals(a, b) => write!(f, "({} == {})", a, b),
+            Expr::Load(addr) => write!(f, "mem[{}]", addr),
+        }
+    }
+}
+
+/// `constant + sum(coefficient * symbol)`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Linear {
+    pub constant: i64,
+    pub terms: BTreeMap<Symbol, i64>,
+}
+
+impl Linear {
+    /// Find values for the symbols so the expression equals `target`.
+    ///
+    /// Every symbol but one needs a range in `bounds`; those are enumerated and the remaining
+    /// symbol is solved for directly. If all symbols are bounded the last one is solved for, and
+    /// its solution must also fall inside its range. Returns the first solution found in
+    /// enumeration order.
+    pub fn solve(
+        &self,
+        target: i64,
+        bounds: &[(Symbol, RangeInclusive<i64>)],
+    ) -> Option<BTreeMap<Symbol, i64>> {
+        let bound = |sym: &Symbol| bounds.iter().find(|(s, _)| s == sym).map(|(_, r)| r.clone());
+
+        let mut unbounded = self.terms.keys().filter(|s| bound(s).is_none());
+        let solved = match (unbounded.next(), unbounded.next()) {
+            (Some(s), None) => *s,
+            (None, None) => match self.terms.keys().last() {
+                Some(s) => *s,
+                None => {
+                    return if self.constant == target { Some(BTreeMap::new()) } else { None };
+                }
+            },
+            _ => return None,
+        };
+        let free = self.terms.keys().filter(|s| **s != solved).cloned().collect::<Vec<_>>();
+        let ranges = free.iter().map(|s| bound(s).unwrap()).collect::<Vec<_>>();
+        let coef = self.terms[&solved];
+
+        // odometer over the free symbols' ranges
+        let mut values = ranges.iter().map(|r| *r.start()).collect::<Vec<_>>();
+        if ranges.iter().any(|r| r.is_empty()) {
+            return None;
+        }
+        loop {
+            let mut rest = target.checked_sub(self.constant)?;
+            for (sym, val) in free.iter().zip(&values) {
+                rest = rest.checked_sub(self.terms[sym].checked_mul(*val)?)?;
+            }
+            if rest.checked_rem(coef) == Some(0) {
+                let val = rest / coef;
+                if bound(&solved).is_none_or(|r| r.contains(&val)) {
+                    let mut solution = free.iter().cloned().zip(values.iter().cloned()).collect::<BTreeMap<_, _>>();
+                    solution.insert(solved, val);
+                    return Some(solution);
+                }
+            }
+
+            let mut i = values.len();
+            loop {
+                if i == 0 {
+                    return None;
+                }
+                i -= 1;
+                if values[i] < *ranges[i].end() {
+                    values[i] += 1;
+                    break;
+                }
+                values[i] = *ranges[i].start();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolicError {
+    /// The instruction at `pc` depends on a symbol.
+    SymbolicInstruction { pc: usize },
+    /// The instruction at `pc` writes to an address that depends on a symbol.
+    SymbolicAddress { pc: usize },
+    /// The jump at `pc` depends on a symbol.
+    SymbolicBranch { pc: usize },
+    /// The relative base adjustment at `pc` depends on a symbol.
+    SymbolicRelativeBase { pc: usize },
+    InvalidInstruction { pc: usize, instruction: i64 },
+    InvalidMode { pc: usize, mode: i32 },
+    /// `addr` is outside of memory, which is as large as a [`crate::Machine`]'s would be.
+    InvalidAddress { pc: usize, addr: i64 },
+    /// Execution did not halt within the allowed number of steps.
+    StepLimit,
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::SymbolicInstruction { pc } => write!(f, "symbolic instruction at pc {}", pc),
+            SymbolicError::SymbolicAddress { pc } => write!(f, "symbolic write address at pc {}", pc),
+            SymbolicError::SymbolicBranch { pc } => write!(f, "symbolic branch condition at pc {}", pc),
+            SymbolicError::SymbolicRelativeBase { pc } => write!(f, "symbolic relative base adjustment at pc {}", pc),
+            SymbolicError::InvalidInstruction { pc, instruction } => {
+                write!(f, "invalid instruction {} at pc {}", instruction, pc)
+            }
+            SymbolicError::InvalidMode { pc, mode } => write!(f, "invalid mode {} at pc {}", mode, pc),
+            SymbolicError::InvalidAddress { pc, addr } => write!(f, "invalid address {} at pc {}", addr, pc),
+            SymbolicError::StepLimit => write!(f, "step limit reached"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SymbolicError {}
+
+/// Executes the same instruction set as [`crate::Machine`] over [`Expr`] values.
+#[derive(Clone, Debug)]
+pub struct SymbolicMachine {
+    memory: Vec<Expr>,
+    // memory grows as it's written, up to this
+    memory_size: usize,
+    program_counter: usize,
+    relative_base: i64,
+    inputs_read: usize,
+    outputs: Vec<Expr>,
+}
+
+impl SymbolicMachine {
+    pub fn new(program: &[i64]) -> Self {
+        SymbolicMachine {
+            memory: program.iter().map(|v| Expr::Const(*v)).collect(),
+            memory_size: MachineBuilder::default().memory_size(program.len()),
+            program_counter: 0,
+            relative_base: 0,
+            inputs_read: 0,
+            outputs: vec![],
+        }
+    }
+
+    /// Replace the contents of `addr` with [`Symbol::Memory`].
+    pub fn symbolize(&mut self, addr: usize) -> Result<(), SymbolicError> {
+        self.store(addr, Expr::Sym(Symbol::Memory(addr)))
+    }
+
+    /// The current contents of `addr`.
+    pub fn cell(&self, addr: usize) -> Expr {
+        self.memory.get(addr).cloned().unwrap_or(Expr::Const(0))
+    }
+
+    /// Everything the program has output so far.
+    pub fn outputs(&self) -> &[Expr] {
+        &self.outputs
+    }
+
+    /// Run until the program halts, giving up after `max_steps` instructions.
+    pub fn execute(&mut self, max_steps: usize) -> Result<(), SymbolicError> {
+        for _ in 0..max_steps {
+            let pc = self.program_counter;
+            let (word, instr) = match self.cell(pc) {
+                Expr::Const(word) => (word, decode(word).ok_or(SymbolicError::InvalidInstruction { pc, instruction: word })?),
+                _ => return Err(SymbolicError::SymbolicInstruction { pc }),
+            };
+            match instr.opcode {
+                99 => return Ok(()),
+                1 | 2 | 7 | 8 => {
+                    let op1 = self.load_with_mode(pc + 1, instr.mode_op1)?;
+                    let op2 = self.load_with_mode(pc + 2, instr.mode_op2)?;
+                    let out = self.address(pc + 3, instr.mode_op3)?;
+                    let val = match instr.opcode {
+                        1 => Expr::add(op1, op2),
+                        2 => Expr::mul(op1, op2),
+                        7 => Expr::less_than(op1, op2),
+                        _ => Expr::equals(op1, op2),
+                    };
+                    self.store(out, val)?;
+                    self.program_counter += 4;
+                }
+                3 => {
+                    let out = self.address(pc + 1, instr.mode_op1)?;
+                    self.store(out, Expr::Sym(Symbol::Input(self.inputs_read)))?;
+                    self.inputs_read += 1;
+                    self.program_counter += 2;
+                }
+                4 => {
+                    let val = self.load_with_mode(pc + 1, instr.mode_op1)?;
+                    self.outputs.push(val);
+                    self.program_counter += 2;
+                }
+                5 | 6 => {
+                    let cond = self.load_with_mode(pc + 1, instr.mode_op1)?;
+                    let cond = cond.as_const().ok_or(SymbolicError::SymbolicBranch { pc })?;
+                    let target = self.load_with_mode(pc + 2, instr.mode_op2)?;
+                    if (cond != 0) == (instr.opcode == 5) {
+                        let target = target.as_const().ok_or(SymbolicError::SymbolicBranch { pc })?;
+                        self.program_counter = self.check_address(target)?;
+                    } else {
+                        self.program_counter += 3;
+                    }
+                }
+                9 => {
+                    let offset = self.load_with_mode(pc + 1, instr.mode_op1)?;
+                    let offset = offset.as_const().ok_or(SymbolicError::SymbolicRelativeBase { pc })?;
+                    self.relative_base = self.relative_base.saturating_add(offset);
+                    self.program_counter += 2;
+                }
+                _ => return Err(SymbolicError::InvalidInstruction { pc, instruction: word }),
+            }
+        }
+        Err(SymbolicError::StepLimit)
+    }
+
+    fn check_address(&self, addr: i64) -> Result<usize, SymbolicError> {
+        match usize::try_from(addr) {
+            Ok(addr) if addr < self.memory_size => Ok(addr),
+            _ => Err(SymbolicError::InvalidAddress { pc: self.program_counter, addr }),
+        }
+    }
+
+    /// The concrete address a write parameter at `addr` points to.
+    fn address(&self, addr: usize, mode: i32) -> Result<usize, SymbolicError> {
+        let base = match mode {
+            0 => 0,
+            2 => self.relative_base,
+            _ => return Err(SymbolicError::InvalidMode { pc: self.program_counter, mode }),
+        };
+        match self.cell(addr) {
+            Expr::Const(target) => self.check_address(base.saturating_add(target)),
+            _ => Err(SymbolicError::SymbolicAddress { pc: self.program_counter }),
+        }
+    }
+
+    fn load_with_mode(&self, addr: usize, mode: i32) -> Result<Expr, SymbolicError> {
+        let base = match mode {
+            0 => 0,
+            1 => return Ok(self.cell(addr)),
+            2 => self.relative_base,
+            _ => return Err(SymbolicError::InvalidMode { pc: self.program_counter, mode }),
+        };
+        match self.cell(addr) {
+            Expr::Const(target) => Ok(self.cell(self.check_address(base.saturating_add(target))?)),
+            symbolic => Ok(Expr::Load(Box::new(Expr::add(Expr::Const(base), symbolic)))),
+        }
+    }
+
+    fn store(&mut self, addr: usize, val: Expr) -> Result<(), SymbolicError> {
+        let addr = self.check_address(i64::try_from(addr).unwrap_or(i64::MAX))?;
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, Expr::Const(0));
+        }
+        self.memory[addr] = val;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // a cut down day 2 program: mem[0] = (noun + verb) * 3 + mem[1] * 5 + 7
+    const DAY2_LIKE: [i64; 26] = [
+        1, 0, 0, 3, // clobbered by the next instruction, reads symbolic addresses
+        1, 1, 2, 3, // mem[3] = noun + verb
+        2, 3, 21, 3, // mem[3] *= 3
+        2, 1, 22, 24, // mem[24] = noun * 5
+        1, 3, 24, 0, // mem[0] = mem[3] + mem[24]
+        99, 3, 5, 0, 0, 0,
+    ];
+
+    #[test]
+    fn it_builds_linear_expressions() {
+        let mut m = SymbolicMachine::new(&DAY2_LIKE);
+        m.symbolize(1).unwrap();
+        m.symbolize(2).unwrap();
+        m.execute(100).unwrap();
+
+        let linear = m.cell(0).linear().unwrap();
+        assert_eq!(linear.constant, 0);
+        assert_eq!(linear.terms[&Symbol::Memory(1)], 8);
+        assert_eq!(linear.terms[&Symbol::Memory(2)], 3);
+    }
+
+    #[test]
+    fn it_solves_for_noun_and_verb() {
+        let mut m = SymbolicMachine::new(&DAY2_LIKE);
+        m.symbolize(1).unwrap();
+        m.symbolize(2).unwrap();
+        m.execute(100).unwrap();
+
+        let bounds = [(Symbol::Memory(1), 0..=99), (Symbol::Memory(2), 0..=99)];
+        let solution = m.cell(0).linear().unwrap().solve(8 * 12 + 3 * 40, &bounds).unwrap();
+        let (noun, verb) = (solution[&Symbol::Memory(1)], solution[&Symbol::Memory(2)]);
+        assert_eq!(8 * noun + 3 * verb, 8 * 12 + 3 * 40);
+
+        // cross check with the concrete machine
+        let (_, mut machine) = crate::Machine::new(DAY2_LIKE.to_vec());
+        machine.set_noun(noun).unwrap();
+        machine.set_verb(verb).unwrap();
+        machine.execute().unwrap();
+        assert_eq!(machine.output(), Ok(8 * 12 + 3 * 40));
+
+        assert_eq!(m.cell(0).linear().unwrap().solve(-1, &bounds), None);
+    }
+
+    #[test]
+    fn it_treats_inputs_as_symbols() {
+        // out = in0 * 4 + in1
+        let mut m = SymbolicMachine::new(&[3, 15, 3, 16, 1002, 15, 4, 15, 1, 15, 16, 15, 4, 15, 99, 0, 0]);
+        m.execute(100).unwrap();
+        assert_eq!(m.outputs().len(), 1);
+        assert_eq!(m.outputs()[0].to_string(), "((in0 * 4) + in1)");
+
+        let solution = m.outputs()[0].linear().unwrap().solve(42, &[(Symbol::Input(1), 0..=3)]).unwrap();
+        assert_eq!(solution[&Symbol::Input(0)], 10);
+        assert_eq!(solution[&Symbol::Input(1)], 2);
+    }
+
+    #[test]
+    fn it_rejects_symbolic_branches() {
+        let mut m = SymbolicMachine::new(&[3, 6, 1005, 6, 0, 99, 0]);
+        assert_eq!(m.execute(100), Err(SymbolicError::SymbolicBranch { pc: 2 }));
+    }
+
+    #[test]
+    fn it_follows_the_relative_base() {
+        let mut m = SymbolicMachine::new(&[109, 1, 204, -1, 99]);
+        m.execute(100).unwrap();
+        assert_eq!(m.outputs(), &[Expr::Const(109)]);
+
+        // out = in0 + mem[13], both addressed relative to the base
+        let mut m = SymbolicMachine::new(&[109, 12, 203, 0, 22201, 0, 1, 1, 204, 1, 99, 0, 0, 0]);
+        m.symbolize(13).unwrap();
+        m.execute(100).unwrap();
+        assert_eq!(m.outputs()[0].to_string(), "(in0 + mem[13])");
+
+        let mut m = SymbolicMachine::new(&[3, 3, 9, 0, 99]);
+        assert_eq!(m.execute(100), Err(SymbolicError::SymbolicRelativeBase { pc: 2 }));
+    }
+
+    #[test]
+    fn it_stays_within_the_memory_a_machine_has() {
+        let mut m = SymbolicMachine::new(&[1101, 1, 1, 1 << 40, 99]);
+        assert_eq!(m.execute(100), Err(SymbolicError::InvalidAddress { pc: 0, addr: 1 << 40 }));
+        let mut m = SymbolicMachine::new(&[4, 5 * 1024, 99]);
+        assert_eq!(m.execute(100), Err(SymbolicError::InvalidAddress { pc: 0, addr: 5 * 1024 }));
+        assert!(SymbolicMachine::new(&[99]).symbolize(1 << 20).is_err());
+
+        let mut m = SymbolicMachine::new(&[11101, 1, 1, 0, 99]);
+        assert_eq!(m.execute(100), Err(SymbolicError::InvalidMode { pc: 0, mode: 1 }));
+        let mut m = SymbolicMachine::new(&[1 << 40]);
+        assert_eq!(m.execute(100), Err(SymbolicError::InvalidInstruction { pc: 0, instruction: 1 << 40 }));
+    }
+}
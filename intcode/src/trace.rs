@@ -0,0 +1,174 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{Machine, Word};
+
+/// One executed instruction, recorded by machines built with
+/// [`crate::MachineBuilder::record_trace`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEvent<W = i64> {
+    /// Number of instructions executed before this one.
+    pub step: u64,
+    pub pc: i64,
+    /// The instruction word at `pc`.
+    pub instruction: W,
+    /// Every write the instruction made, in order, as `(address, value)`.
+    pub writes: Vec<(i64, W)>,
+    pub input: Option<W>,
+    pub output: Option<W>,
+}
+
+/// One line, like `step 1 pc 2 1001 [9]=42 in 41 out 42`.
+impl<W: fmt::Display> fmt::Display for TraceEvent<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "step {} pc {} {}", self.step, self.pc, self.instruction)?;
+        for (addr, val) in &self.writes {
+            write!(f, " [{}]={}", addr, val)?;
+        }
+        if let Some(input) = &self.input {
+            write!(f, " in {}", input)?;
+        }
+        if let Some(output) = &self.output {
+            write!(f, " out {}", output)?;
+        }
+        Ok(())
+    }
+}
+
+/// The index of the first event that differs between two traces, or where the shorter one ends.
+/// None if they're the same.
+pub fn first_divergence<W: PartialEq>(a: &[TraceEvent<W>], b: &[TraceEvent<W>]) -> Option<usize> {
+    match a.iter().zip(b).position(|(a, b)| a != b) {
+        Some(index) => Some(index),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+/// The events around `index` as a diff: up to `context` shared events before it, then up to
+/// `context + 1` events from `index` on in each trace, `a`'s marked `-` and `b`'s `+`.
+pub fn format_divergence<W: fmt::Display>(
+    a: &[TraceEvent<W>],
+    b: &[TraceEvent<W>],
+    index: usize,
+    context: usize,
+) -> String {
+    let mut report = format!("first difference at event {}\n", index);
+    let end = index.min(a.len());
+    for event in &a[index.saturating_sub(context).min(end)..end] {
+        report += &format!("  {}\n", event);
+    }
+    for (sign, trace) in [('-', a), ('+', b)] {
+        let end = (index + context + 1).min(trace.len());
+        for event in trace.get(index..end).unwrap_or(&[]) {
+            report += &format!("{} {}\n", sign, event);
+        }
+        if end < index + context + 1 {
+            report += &format!("{} (end of trace)\n", sign);
+        }
+    }
+    report
+}
+
+impl<W: Word> Machine<W> {
+    /// Every instruction executed so far, oldest first. Empty unless the machine records a trace.
+    pub fn trace_events(&self) -> &[TraceEvent<W>] {
+        self.trace_events.as_deref().unwrap_or(&[])
+    }
+
+    /// Hand over the events recorded so far and start over with an empty trace.
+    pub fn take_trace_events(&mut self) -> Vec<TraceEvent<W>> {
+        self.trace_events.as_mut().map(core::mem::take).unwrap_or_default()
+    }
+
+    pub(crate) fn begin_event(&mut self) {
+        let pc = self.program_counter;
+        let instruction = self.memory.get(pc as usize).cloned().unwrap_or_else(|| W::from_i64(0));
+        self.current_event = Some(TraceEvent {
+            step: self.instructions_executed,
+            pc,
+            instruction,
+            writes: vec![],
+            input: None,
+            output: None,
+        });
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{first_divergence, format_divergence};
+    use crate::{MachineBuilder, Status, TraceEvent};
+
+    #[test]
+    fn it_records_executed_instructions() {
+        // out = in + 1
+        let (_, mut m) = MachineBuilder::new().trace(false).record_trace(true).build(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+        assert_eq!(m.execute(), Ok(Status::NeedsInput));
+        assert!(m.trace_events().is_empty());
+
+        m.set_input_string("41\n".to_string());
+        m.execute().unwrap();
+        let event = |step, pc, instruction, writes: Vec<(i64, i64)>, input, output| {
+            TraceEvent { step, pc, instruction, writes, input, output }
+        };
+        assert_eq!(m.take_trace_events(), vec![
+            event(0, 0, 3, vec![(9, 41)], Some(41), None),
+            event(1, 2, 1001, vec![(9, 42)], None, None),
+            event(2, 6, 4, vec![], None, Some(42)),
+            event(3, 8, 99, vec![], None, None),
+        ]);
+        assert!(m.trace_events().is_empty());
+
+        // off by default
+        let (_, mut m) = MachineBuilder::new().trace(false).build(vec![104, 1, 99]);
+        m.execute().unwrap();
+        assert!(m.trace_events().is_empty());
+    }
+
+    #[test]
+    fn it_finds_where_traces_diverge() {
+        let run = |input: &str| {
+            let (_, mut m) = MachineBuilder::new().trace(false).record_trace(true).build(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+            m.set_input_string(input.to_string());
+            m.execute().unwrap();
+            m.take_trace_events()
+        };
+        let (a, b) = (run("41\n"), run("1\n"));
+        assert_eq!(first_divergence(&a, &a), None);
+        assert_eq!(first_divergence(&a, &a[..2]), Some(2));
+        assert_eq!(first_divergence(&a, &b), Some(0));
+        assert_eq!(
+            format_divergence(&a, &b, 1, 1),
+            "first difference at event 1
+  step 0 pc 0 3 [9]=41 in 41
+- step 1 pc 2 1001 [9]=42
+- step 2 pc 6 4 out 42
++ step 1 pc 2 1001 [9]=2
++ step 2 pc 6 4 out 2
+"
+        );
+        assert_eq!(
+            format_divergence(&a, &a[..2], 2, 1),
+            "first difference at event 2
+  step 1 pc 2 1001 [9]=42
+- step 2 pc 6 4 out 42
+- step 3 pc 8 99
++ (end of trace)
+"
+        );
+        // an index past the end of the shorter trace
+        assert_eq!(
+            format_divergence(&a[..1], &a, 3, 1),
+            "first difference at event 3
+- (end of trace)
++ step 3 pc 8 99
++ (end of trace)
+"
+        );
+    }
+}
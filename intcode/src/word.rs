@@ -0,0 +1,108 @@
+//! Memory cell types.
+//!
+//! [`crate::Machine`] works on any [`Word`]. `i64` is the default and is what the puzzles ask
+//! for; `i128` and, with the `bigint` feature, `num_bigint::BigInt` give programs more room.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+pub trait Word: Clone + PartialEq + PartialOrd + fmt::Debug + fmt::Display + Send + 'static {
+    fn from_i64(val: i64) -> Self;
+
+    /// None if the value doesn't fit in an i64.
+    fn to_i64(&self) -> Option<i64>;
+
+    /// The value clamped to the i64 range, for addresses and error reports.
+    fn to_i64_saturating(&self) -> i64 {
+        match self.to_i64() {
+            Some(val) => val,
+            None if *self < Self::from_i64(0) => i64::MIN,
+            None => i64::MAX,
+        }
+    }
+
+    /// Parse a decimal number, as read from an input string.
+    fn parse(s: &str) -> Option<Self>;
+
+    fn wrapping_add(&self, other: &Self) -> Self;
+    fn wrapping_mul(&self, other: &Self) -> Self;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::from_i64(0)
+    }
+}
+
+macro_rules! primitive_word {
+    ($t:ty) => {
+        impl Word for $t {
+            fn from_i64(val: i64) -> Self {
+                val as $t
+            }
+
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            fn parse(s: &str) -> Option<Self> {
+                s.parse().ok()
+            }
+
+            fn wrapping_add(&self, other: &Self) -> Self {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            fn wrapping_mul(&self, other: &Self) -> Self {
+                <$t>::wrapping_mul(*self, *other)
+            }
+
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+        }
+    };
+}
+
+primitive_word!(i64);
+primitive_word!(i128);
+
+/// Never overflows, so wrapping and checked arithmetic behave the same.
+#[cfg(feature = "bigint")]
+impl Word for num_bigint::BigInt {
+    fn from_i64(val: i64) -> Self {
+        val.into()
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sign() == num_bigint::Sign::NoSign
+    }
+}